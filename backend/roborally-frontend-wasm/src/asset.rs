@@ -190,6 +190,25 @@ impl From<GameMap> for AssetMap {
                                 },
                             },
                         ],
+                        Custom(custom_i) => vec![
+                            Asset {
+                                value: "floor.jpg".to_owned(),
+                                is_text: false,
+                                effects: Effects {
+                                    scale: 0.25,
+                                    ..Effects::random_rotate_flip()
+                                },
+                            },
+                            // there are no textures for custom tiles, so at least show their name
+                            Asset {
+                                value: m.custom_tiles[custom_i as usize].clone(),
+                                is_text: true,
+                                effects: Effects {
+                                    translate: Some((4.0, 22.0)),
+                                    ..Effects::default()
+                                },
+                            },
+                        ],
                         PushPanel(dir, div, remainder) => {
                             let (text_direction, translate_y) = if dir == Direction::Down {
                                 (Direction::Up, 33.5)
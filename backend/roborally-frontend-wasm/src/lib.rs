@@ -36,9 +36,12 @@ use crate::asset::AssetMap;
 use roborally_structs::{
     card::Card,
     game_map::GameMap,
-    game_state::player_public_state::{PlayerPublicState, PlayerPublicStateArray},
+    game_state::{
+        player_public_state::{PlayerPublicState, PlayerPublicStateArray},
+        RoundSettings,
+    },
     logging::{self, info},
-    transport::{wrapper::ServerMessageWrapper, ClientMessage, ServerMessage},
+    transport::{wrapper::ServerMessageWrapper, ClientMessage, Hello, ServerMessage},
 };
 
 use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
@@ -68,6 +71,78 @@ pub fn create_program_cards_message(cards: Vec<u8>) -> Vec<u8> {
     .unwrap()
 }
 
+#[wasm_bindgen]
+#[must_use]
+pub fn create_hello_message() -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::Hello(Hello::current())).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_set_ready_message(ready: bool) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::SetReady(ready)).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_switch_seat_message(seat: usize) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::SwitchSeat(seat)).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_change_settings_message(round_registers: usize, draw_cards: usize) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::ChangeSettings(RoundSettings {
+        round_registers,
+        draw_cards,
+    }))
+    .unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_kick_message(seat: usize) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::Kick(seat)).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_start_game_message() -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::StartGame).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_set_paused_message(paused: bool) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::SetPaused(paused)).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_abort_game_message() -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::AbortGame).unwrap()
+}
+
+#[wasm_bindgen]
+#[must_use]
+pub fn create_chat_message(text: String) -> Vec<u8> {
+    rmp_serde::to_vec(&ClientMessage::Chat(text)).unwrap()
+}
+
+/// Longest chat message the server accepts, in characters
+#[wasm_bindgen]
+#[must_use]
+pub fn max_chat_message_length() -> usize {
+    roborally_structs::chat::MAX_CHAT_MESSAGE_LENGTH
+}
+
+/// Whether the protocol version chosen by the server is the one this client speaks
+#[wasm_bindgen]
+#[must_use]
+pub fn is_supported_protocol_version(version: u32) -> bool {
+    version == roborally_structs::transport::PROTOCOL_VERSION
+}
+
 #[wasm_bindgen]
 pub fn parse_map(bytes: &[u8]) -> Result<ParsedMap, JsValue> {
     rmp_serde::from_slice::<GameMap>(bytes)
@@ -0,0 +1,100 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use roborally_structs::logging::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::game::CardInitializationDefinition;
+
+/// One version of a named set of cards, loaded from a JSON file in the card sets directory
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CardSet {
+    pub name: String,
+    pub version: u32,
+    pub again_count: usize,
+    pub cards: Vec<CardInitializationDefinition>,
+}
+
+/// Which card set a new game should use
+#[derive(Deserialize)]
+pub struct CardSetRef {
+    pub name: String,
+    /// Latest version is used if not given
+    #[serde(default)]
+    pub version: Option<u32>,
+}
+
+/// All card sets available for new games, by name and version
+#[derive(Default)]
+pub struct CardSetRegistry {
+    sets: HashMap<String, BTreeMap<u32, CardSet>>,
+}
+
+impl CardSetRegistry {
+    /// Loads all `.json` files from the directory, logging the ones that can't be loaded
+    pub fn load_dir(dir: &Path) -> Self {
+        let mut registry = Self::default();
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("Error reading card sets directory {}: {e}", dir.display());
+                return registry;
+            }
+        };
+        for path in entries.filter_map(|e| Some(e.ok()?.path())) {
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let set = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| serde_json::from_str::<CardSet>(&text).map_err(|e| e.to_string()));
+            match set {
+                Ok(set) => {
+                    if let Err(e) = registry.insert(set) {
+                        error!("Error loading card set {}: {e}", path.display());
+                    }
+                }
+                Err(e) => error!("Error loading card set {}: {e}", path.display()),
+            }
+        }
+        info!("Loaded {} card sets", registry.sets.len());
+        registry
+    }
+
+    fn insert(&mut self, set: CardSet) -> Result<(), String> {
+        let versions = self.sets.entry(set.name.clone()).or_default();
+        if versions.contains_key(&set.version) {
+            return Err(format!(
+                "Version {} of card set {} is defined twice",
+                set.version, set.name
+            ));
+        }
+        versions.insert(set.version, set);
+        Ok(())
+    }
+
+    pub fn get(&self, set_ref: &CardSetRef) -> Result<&CardSet, String> {
+        let versions = self
+            .sets
+            .get(&set_ref.name)
+            .ok_or_else(|| format!("Unknown card set {}", set_ref.name))?;
+        set_ref.version.map_or_else(
+            || Ok(versions.values().next_back().unwrap()),
+            |version| {
+                versions
+                    .get(&version)
+                    .ok_or_else(|| format!("Card set {} has no version {version}", set_ref.name))
+            },
+        )
+    }
+
+    /// All versions of all sets, sorted by name and version
+    pub fn list(&self) -> Vec<&CardSet> {
+        let mut list: Vec<&CardSet> = self.sets.values().flat_map(BTreeMap::values).collect();
+        list.sort_by(|a, b| (&a.name, a.version).cmp(&(&b.name, b.version)));
+        list
+    }
+}
@@ -0,0 +1,124 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use roborally_structs::{
+    chat::{ChatMessage, MAX_CHAT_MESSAGE_LENGTH},
+    transport::{Capability, ErrorCode, GameError, ServerMessage},
+};
+use tokio::time::Instant;
+
+use crate::{
+    game::Game,
+    game_connection::{PlayerConnection, SocketMessage},
+};
+
+/// How many messages are kept to be replayed to players who connect later
+const HISTORY_LENGTH: usize = 200;
+/// A player can send at most this many messages within [`RATE_LIMIT_WINDOW`]
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Times of a player's recent chat messages
+#[derive(Default)]
+pub struct RateLimiter(Mutex<VecDeque<Instant>>);
+
+impl RateLimiter {
+    /// Records a message, unless the player already sent too many recently
+    fn try_acquire(&self) -> bool {
+        let mut times = self.0.lock().unwrap();
+        let now = Instant::now();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > RATE_LIMIT_WINDOW)
+        {
+            times.pop_front();
+        }
+        if times.len() >= RATE_LIMIT_MESSAGES {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+impl Game {
+    /// Adds the message to the history and sends it to everyone connected
+    pub fn post_chat(&self, msg: ChatMessage) {
+        for conn_lock in &self.player_connections {
+            let Some(conn) = conn_lock.read().unwrap().upgrade() else {
+                continue;
+            };
+            if conn.has_capability(Capability::Chat) {
+                conn.sender
+                    .send(SocketMessage::SendMessage(ServerMessage::Chat(msg.clone())))
+                    .unwrap();
+            }
+        }
+        let mut history = self.chat.lock().unwrap();
+        if history.len() >= HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(msg);
+    }
+
+    /// Posts something that happened in the game, if the game was created with [`crate::game::NewGameData::chat_events`]
+    pub fn post_chat_event(&self, text: String) {
+        if self.chat_events {
+            self.post_chat(ChatMessage {
+                seat: None,
+                author: String::new(),
+                text,
+            });
+        }
+    }
+
+    /// Name of the player connected to the seat, for chat events
+    pub fn seat_name(&self, seat: usize) -> String {
+        self.player_connections[seat]
+            .read()
+            .unwrap()
+            .upgrade()
+            .map_or_else(|| format!("Seat {}", seat + 1), |c| c.player_name.clone())
+    }
+}
+
+impl PlayerConnection {
+    /// Replays the chat history, if the client supports chat
+    pub fn send_chat_history(&self) {
+        if !self.has_capability(Capability::Chat) {
+            return;
+        }
+        for msg in self.game.chat.lock().unwrap().iter() {
+            self.sender
+                .send(SocketMessage::SendMessage(ServerMessage::Chat(msg.clone())))
+                .unwrap();
+        }
+    }
+
+    pub fn chat(&self, text: &str) -> Result<(), GameError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(GameError::new(
+                ErrorCode::InvalidChatMessage,
+                "Chat message is empty",
+            ));
+        }
+        if text.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+            return Err(GameError::new(
+                ErrorCode::InvalidChatMessage,
+                format!("Chat messages can have at most {MAX_CHAT_MESSAGE_LENGTH} characters"),
+            ));
+        }
+        if !self.chat_rate_limit.try_acquire() {
+            return Err(GameError::new(
+                ErrorCode::ChatRateLimited,
+                "You are sending messages too fast, wait a few seconds",
+            ));
+        }
+        self.game.post_chat(ChatMessage {
+            seat: Some(self.seat()),
+            author: self.player_name.clone(),
+            text: text.to_owned(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use roborally_structs::logging::{LogFilter, LogFormat};
+use serde::Deserialize;
+
+use crate::map_generator::GenerateMapArgs;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Settings of the server, see [`Cli`] for where they come from
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub maps_dir: PathBuf,
+    pub card_sets_dir: PathBuf,
+    /// Static files of the web client
+    pub www_dir: PathBuf,
+    /// Defaults to all interfaces if a port is given, and to localhost otherwise
+    pub address: Option<IpAddr>,
+    /// 8080 if not given
+    pub port: Option<u16>,
+    /// Games are deleted once nobody has been connected to them for this long
+    pub game_expiry_secs: u64,
+    pub ping_interval_secs: u64,
+    /// Connections that don't answer pings for this long are closed
+    pub ping_timeout_secs: u64,
+    pub max_game_name_length: usize,
+    /// Maps uploaded without `persist` are kept in memory, so their number is limited
+    pub max_uploaded_maps: usize,
+    /// Rhai operations allowed in one call into a card, map or mode script
+    pub script_max_operations: u64,
+    /// Enables the `/api/admin` endpoints, requests have to send it as `Authorization: Bearer <token>`
+    pub admin_token: Option<String>,
+    /// Levels of log records per module, e.g. `info,roborally_server::game_connection=debug`
+    pub log: LogFilter,
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            maps_dir: "maps".into(),
+            card_sets_dir: "cards".into(),
+            www_dir: "www".into(),
+            address: None,
+            port: None,
+            game_expiry_secs: 300,
+            ping_interval_secs: 10,
+            ping_timeout_secs: 20,
+            max_game_name_length: 50,
+            max_uploaded_maps: 100,
+            script_max_operations: 20000,
+            admin_token: None,
+            log: LogFilter::default(),
+            log_format: LogFormat::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn bind_address(&self) -> SocketAddr {
+        let default_address = if self.port.is_some() {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        };
+        SocketAddr::new(
+            self.address.unwrap_or_else(|| default_address.into()),
+            self.port.unwrap_or(8080),
+        )
+    }
+
+    pub const fn game_expiry(&self) -> Duration {
+        Duration::from_secs(self.game_expiry_secs)
+    }
+
+    pub const fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.ping_interval_secs)
+    }
+
+    pub const fn ping_timeout(&self) -> Duration {
+        Duration::from_secs(self.ping_timeout_secs)
+    }
+}
+
+/// Settings the server was started with, or the defaults if they weren't loaded (e.g. in the
+/// `generate-map` command)
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Command line interface of the server
+///
+/// Settings are taken from the flags, then from environment variables, then from the config file
+#[derive(Parser)]
+#[command(about = "Server of the RoboRally game")]
+pub struct Cli {
+    /// TOML file with the settings, its keys are the names of the flags with underscores
+    #[arg(long, env = "ROBORALLY_CONFIG")]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Prints a random map in the map file format
+    GenerateMap(GenerateMapArgs),
+}
+
+/// Settings from [`Config`] given on the command line or in the environment
+#[derive(Args)]
+struct ConfigOverrides {
+    #[arg(long, env = "ROBORALLY_MAPS_DIR")]
+    maps_dir: Option<PathBuf>,
+    #[arg(long, env = "ROBORALLY_CARD_SETS_DIR")]
+    card_sets_dir: Option<PathBuf>,
+    #[arg(long, env = "ROBORALLY_WWW_DIR")]
+    www_dir: Option<PathBuf>,
+    #[arg(long, env = "ROBORALLY_ADDRESS")]
+    address: Option<IpAddr>,
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+    #[arg(long, env = "ROBORALLY_GAME_EXPIRY_SECS")]
+    game_expiry_secs: Option<u64>,
+    #[arg(long, env = "ROBORALLY_PING_INTERVAL_SECS")]
+    ping_interval_secs: Option<u64>,
+    #[arg(long, env = "ROBORALLY_PING_TIMEOUT_SECS")]
+    ping_timeout_secs: Option<u64>,
+    #[arg(long, env = "ROBORALLY_MAX_GAME_NAME_LENGTH")]
+    max_game_name_length: Option<usize>,
+    #[arg(long, env = "ROBORALLY_MAX_UPLOADED_MAPS")]
+    max_uploaded_maps: Option<usize>,
+    #[arg(long, env = "ROBORALLY_SCRIPT_MAX_OPERATIONS")]
+    script_max_operations: Option<u64>,
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    #[arg(long, env = "ROBORALLY_LOG")]
+    log: Option<LogFilter>,
+    /// `text` or `json`
+    #[arg(long, env = "ROBORALLY_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+impl ConfigOverrides {
+    fn apply(self, config: &mut Config) {
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(if let Some(value) = self.$field {
+                    config.$field = value;
+                })*
+            };
+        }
+        apply!(
+            maps_dir,
+            card_sets_dir,
+            www_dir,
+            game_expiry_secs,
+            ping_interval_secs,
+            ping_timeout_secs,
+            max_game_name_length,
+            max_uploaded_maps,
+            script_max_operations,
+            log,
+            log_format
+        );
+        config.address = self.address.or(config.address);
+        config.port = self.port.or(config.port);
+        config.admin_token = self.admin_token.or_else(|| config.admin_token.take());
+    }
+}
+
+impl Cli {
+    /// Reads the config file, applies the flags and environment variables over it, and makes the
+    /// result available through [`config`]
+    ///
+    /// Returns the subcommand to run, if any
+    pub fn load_config(self) -> Result<Option<Command>, String> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Can't read config file {}: {e}", path.display()))?;
+                toml::from_str(&text)
+                    .map_err(|e| format!("Invalid config file {}: {e}", path.display()))?
+            }
+            None => Config::default(),
+        };
+        self.overrides.apply(&mut config);
+        config.admin_token = config.admin_token.filter(|t| !t.is_empty());
+        if config.ping_interval_secs == 0 {
+            return Err("Ping interval must be at least 1 second".to_owned());
+        }
+        // rhai treats 0 as no limit
+        if config.script_max_operations == 0 {
+            return Err("Script operation limit must be at least 1".to_owned());
+        }
+        if config.ping_timeout_secs <= config.ping_interval_secs {
+            return Err("Ping timeout must be longer than the ping interval".to_owned());
+        }
+        CONFIG
+            .set(config)
+            .map_err(|_| "Config was already loaded".to_owned())?;
+        Ok(self.command)
+    }
+}
@@ -0,0 +1,152 @@
+use roborally_structs::{
+    game_map::GameMap,
+    position::{Direction, Position},
+    tile::{DirectionBools, Grid, Tile},
+    tile_type::TileType,
+};
+
+use crate::parser::{Parse, Unparse};
+
+/// A course built from other maps ("boards"), each placed at an offset, mirrored and rotated
+///
+/// Composing it produces a single [`GameMap`], so the game itself doesn't know about courses at all.
+#[derive(Debug)]
+pub struct Course {
+    pub name: String,
+    pub size: Position,
+    /// If not given, the antenna of the first board is used. Antennas of other boards are left
+    /// in place as walled-off tiles.
+    pub antenna: Option<Position>,
+    /// If not given, the reboot token of the first board is used
+    pub reboot_token: Option<(Position, Direction)>,
+    /// If not given, checkpoints of all boards are used, in the order of the boards
+    pub checkpoints: Option<Vec<Position>>,
+    pub boards: Vec<CourseBoard>,
+}
+
+#[derive(Debug)]
+pub struct CourseBoard {
+    pub map_name: String,
+    /// Position of the top left corner of the (rotated) board in the course
+    pub offset: Position,
+    /// Number of 90 degree clockwise rotations
+    pub rotation: u8,
+    /// Mirroring applied before the rotation, `true` for horizontal
+    pub mirror: Option<bool>,
+}
+
+impl Course {
+    /// Joins all boards into one map, which is then validated the same way as any map loaded from a file
+    pub fn compose<'a>(
+        &self,
+        get_map: impl Fn(&str) -> Option<&'a GameMap>,
+    ) -> Result<GameMap, String> {
+        let size = self.size;
+        let area = usize::try_from(size.x).map_err(|_| "negative course width".to_owned())?
+            * usize::try_from(size.y).map_err(|_| "negative course height".to_owned())?;
+        let mut tiles = Grid::new(
+            vec![
+                Tile {
+                    typ: TileType::Void,
+                    walls: DirectionBools::default(),
+                };
+                area
+            ],
+            size,
+        )?;
+        let mut covered_by: Vec<Option<&str>> = vec![None; area];
+        let mut first_board: Option<GameMap> = None;
+        let mut checkpoints = Vec::new();
+        let mut spawn_points = Vec::new();
+        let mut lasers = Vec::new();
+        let mut custom_tiles: Vec<String> = Vec::new();
+        let mut script = None;
+
+        for board in &self.boards {
+            let mut map = get_map(&board.map_name)
+                .ok_or_else(|| format!("unknown board {}", board.map_name))?
+                .clone();
+            if let Some(horizontal) = board.mirror {
+                map = map.mirrored(horizontal);
+            }
+            for _ in 0..board.rotation {
+                map = map.rotated();
+            }
+            let shift = |pos: Position| Position {
+                x: pos.x + board.offset.x,
+                y: pos.y + board.offset.y,
+            };
+            let shift_with_dir = |(pos, dir): &(Position, Direction)| (shift(*pos), *dir);
+
+            if let Some(board_script) = &map.script {
+                if script.is_some() {
+                    return Err("only one board of a course can have a script".to_owned());
+                }
+                script = Some(board_script.clone());
+            }
+            // custom tile indices are per map, so they have to be renumbered
+            let custom_indices = map
+                .custom_tiles
+                .iter()
+                .map(|custom_name| {
+                    let i = custom_tiles
+                        .iter()
+                        .position(|t| t == custom_name)
+                        .unwrap_or_else(|| {
+                            custom_tiles.push(custom_name.clone());
+                            custom_tiles.len() - 1
+                        });
+                    u8::try_from(i).map_err(|_| "too many custom tile types".to_owned())
+                })
+                .collect::<Result<Vec<u8>, String>>()?;
+
+            let board_size = map.tiles.size();
+            for y in 0..board_size.y {
+                for x in 0..board_size.x {
+                    let target = shift(Position { x, y });
+                    let Some(target_tile) = tiles.get_mut(target) else {
+                        return Err(format!(
+                            "board {} doesn't fit into the course",
+                            board.map_name
+                        ));
+                    };
+                    let covered = &mut covered_by[(target.y * size.x + target.x) as usize];
+                    if let Some(other) = covered {
+                        return Err(format!(
+                            "boards {other} and {} overlap at {},{}",
+                            board.map_name, target.x, target.y
+                        ));
+                    }
+                    *covered = Some(&board.map_name);
+                    *target_tile = *map.tiles.get(Position { x, y }).unwrap();
+                    if let TileType::Custom(i) = &mut target_tile.typ {
+                        *i = custom_indices[*i as usize];
+                    }
+                }
+            }
+
+            checkpoints.extend(map.checkpoints.iter().map(|pos| shift(*pos)));
+            spawn_points.extend(map.spawn_points.iter().map(shift_with_dir));
+            lasers.extend(map.lasers.iter().map(shift_with_dir));
+            if first_board.is_none() {
+                map.antenna = shift(map.antenna);
+                map.reboot_token = shift_with_dir(&map.reboot_token);
+                first_board = Some(map);
+            }
+        }
+
+        let first_board = first_board.ok_or_else(|| "course has no boards".to_owned())?;
+        let composed = GameMap {
+            name: self.name.clone(),
+            tiles,
+            antenna: self.antenna.unwrap_or(first_board.antenna),
+            reboot_token: self.reboot_token.unwrap_or(first_board.reboot_token),
+            checkpoints: self.checkpoints.clone().unwrap_or(checkpoints),
+            spawn_points,
+            lasers,
+            custom_tiles,
+            script,
+        };
+        GameMap::parse(&composed.unparse(), &self.name).map_err(|e| e.to_string())
+    }
+}
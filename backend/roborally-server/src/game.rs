@@ -1,29 +1,54 @@
 use std::{
+    collections::{HashMap, VecDeque},
     iter::repeat_with,
     mem,
-    sync::{Arc, Mutex, RwLock, Weak},
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock, Weak},
     time::Duration,
 };
 
 use rand::{prelude::SliceRandom, thread_rng};
-use rhai::{exported_module, Engine, Scope, AST};
+use rhai::{exported_module, Dynamic, Engine, FuncArgs, Scope, AST};
 use roborally_structs::{
     card::Card,
+    chat::ChatMessage,
+    game_log::{LogEntry, LogLevel},
     game_map::GameMap,
-    game_state::{phase::RegisterMovePhase, GameStatusInfo, GeneralState},
-    transport::ServerMessage,
+    game_state::{
+        phase::RegisterMovePhase, player_public_state::PlayerPublicState, GameStatusInfo,
+        GeneralState, RoundSettings,
+    },
+    logging::info,
+    position::Position,
+    tile_type::TileType,
+    transport::{ErrorCode, GameError, ServerMessage},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
 
 use crate::{
+    card_sets::{CardSetRef, CardSetRegistry},
     game_connection::{PlayerConnection, SocketMessage},
+    game_log::{GameLog, LogVisibility, ScriptContext},
     game_state::GameState,
+    lobby::Lobby,
+    map_generator::MapGeneratorParams,
+    metrics::METRICS,
+    moderation::PauseGate,
     player::Player,
     rhai_api::game_api,
+    savefile::Savefile,
+    script_limits::{describe_script_error, limit_engine, ScriptDeadline},
+    script_store::ScriptStore,
 };
 
-#[derive(Deserialize)]
+/// How many times in a row map hooks can move robots to new tiles, before we give up on
+/// the hooks settling down
+const MAX_HOOK_ROUNDS: usize = 20;
+
+/// Custom cards are sent to clients as a `u8` after `Again` and `SPAM`, see [`Card::to_number`]
+pub const MAX_CARD_DEFINITIONS: usize = u8::MAX as usize - 1;
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct CardInitializationDefinition {
     pub asset: String,
     pub code: String,
@@ -35,30 +60,124 @@ pub struct CardInitializationDefinition {
 pub struct NewGameData {
     pub map_name: String,
     pub name: String,
-    player_count: usize,
-    again_count: usize,
-    card_definitions: Vec<CardInitializationDefinition>,
-    round_registers: usize,
-    draw_cards: usize,
+    /// If given, `map_name` is ignored and a new map is generated for this game
+    #[serde(default)]
+    pub random_map: Option<MapGeneratorParams>,
+    pub player_count: usize,
+    /// Cards of this set are used, with `card_definitions` added to them.
+    /// Cards in `card_definitions` replace cards with the same name from the set.
+    #[serde(default)]
+    pub card_set: Option<CardSetRef>,
+    /// Taken from the card set if not given
+    #[serde(default)]
+    pub again_count: Option<usize>,
+    #[serde(default)]
+    pub card_definitions: Vec<CardInitializationDefinition>,
+    #[serde(default)]
+    pub log_visibility: LogVisibility,
+    pub round_registers: usize,
+    pub draw_cards: usize,
+    /// Script with the rules of the game, see [`Game::check_winner`]
+    #[serde(default)]
+    pub mode_script: Option<String>,
+    /// Post reboots, reached checkpoints and players connecting and leaving into the chat
+    #[serde(default)]
+    pub chat_events: bool,
 }
 
-pub struct Game {
-    pub map: GameMap,
+impl NewGameData {
+    /// Settings for a throwaway game used to try out cards, with a single register and enough
+    /// Again cards to pass the deck size checks
+    pub const fn sandbox(
+        map_name: String,
+        player_count: usize,
+        card_definitions: Vec<CardInitializationDefinition>,
+    ) -> Self {
+        Self {
+            map_name,
+            name: String::new(),
+            random_map: None,
+            player_count,
+            card_set: None,
+            again_count: Some(3),
+            card_definitions,
+            log_visibility: LogVisibility::Everyone,
+            round_registers: 1,
+            draw_cards: 1,
+            mode_script: None,
+            chat_events: false,
+        }
+    }
+
+    /// Replaces the card set reference with the cards from it, see [`Self::card_set`]
+    pub fn resolve_card_set(&mut self, card_sets: &CardSetRegistry) -> Result<(), String> {
+        let Some(set_ref) = self.card_set.take() else {
+            return Ok(());
+        };
+        let set = card_sets.get(&set_ref)?;
+        let mut cards = set.cards.clone();
+        for card in mem::take(&mut self.card_definitions) {
+            if let Some(existing) = cards.iter_mut().find(|c| c.name == card.name) {
+                *existing = card;
+            } else {
+                cards.push(card);
+            }
+        }
+        self.card_definitions = cards;
+        self.again_count = self.again_count.or(Some(set.again_count));
+        Ok(())
+    }
+}
+
+/// Compiled scripts of a game
+///
+/// The round settings are constants in their scope, so they are compiled again when the
+/// settings change in the lobby
+#[derive(Default)]
+pub struct Scripts {
     /// Asset url, AST, scope
     pub cards: Vec<(String, Arc<AST>, Mutex<Scope<'static>>)>,
+    /// Compiled script of the map, if it has one
+    map: Option<(AST, Mutex<Scope<'static>>)>,
+    /// Compiled mode script, if the game has one
+    mode: Option<(AST, Mutex<Scope<'static>>)>,
+}
+
+pub struct Game {
+    /// Key in the list of games, attached to log records
+    pub name: String,
+    pub map: GameMap,
+    pub scripts: RwLock<Scripts>,
     pub last_nobody_connected: Mutex<Option<Instant>>,
     pub player_connections: Vec<RwLock<Weak<PlayerConnection>>>,
     pub engine: Arc<Engine>,
+    /// Time limit of the script call currently running on `engine`
+    script_deadline: ScriptDeadline,
     pub state: Arc<RwLock<GameState>>,
     /// Anything modifying the game state should lock this mutex before doing so.
     ///
     /// This is used alongside the state `RwLock`, because unfortunately `.run()` is unable to hold the `RwLock` the entire time
-    running_guard: tokio::sync::Mutex<()>,
-    pub log: Arc<Mutex<String>>,
-    pub round_registers: usize,
-    pub draw_cards: usize,
+    ///
+    /// The round is evaluated on a blocking thread, which owns the guard until it's done
+    running_guard: Arc<tokio::sync::Mutex<()>>,
+    pub log: Arc<GameLog>,
+    pub log_visibility: LogVisibility,
+    /// Can only change in the lobby
+    settings: RwLock<RoundSettings>,
     pub player_count: usize,
     pub card_pack_size: usize,
+    /// Settings the game was created with, kept for savefiles
+    pub again_count: usize,
+    pub card_definitions: Vec<CardInitializationDefinition>,
+    pub mode_script_source: Option<String>,
+    /// Recent chat messages, replayed to players who connect
+    pub chat: Mutex<VecDeque<ChatMessage>>,
+    pub chat_events: bool,
+    pub pause: PauseGate,
+    /// Set once the game is ended by the host or an admin, see [`Game::abort`]
+    pub aborted: AtomicBool,
+    /// Names of kicked players and when they were kicked, see [`Game::check_not_kicked`]
+    pub kicked_players: Mutex<HashMap<String, std::time::Instant>>,
 }
 
 impl Game {
@@ -66,106 +185,233 @@ impl Game {
         map: GameMap,
         NewGameData {
             map_name: _,
-            name: _,
+            name,
+            random_map: _,
             player_count,
+            card_set: _,
             again_count,
             card_definitions,
+            log_visibility,
             round_registers,
             draw_cards,
+            mode_script,
+            chat_events,
         }: NewGameData,
     ) -> Result<Arc<Self>, String> {
+        let again_count = again_count.unwrap_or_default();
         if map.spawn_points.len() < player_count {
             return Err("Not enough spawn points on map".to_owned());
         }
-
-        if round_registers > draw_cards {
-            return Err("Too few cards to draw".to_owned());
-        }
-
-        if round_registers < 1 {
-            return Err("Too few registers per round".to_owned());
-        }
-
-        if again_count + card_definitions.iter().map(|c| c.count).sum::<usize>() <= draw_cards + 1 {
-            return Err("Too many cards to draw".to_owned());
+        if card_definitions.len() > MAX_CARD_DEFINITIONS {
+            return Err(format!(
+                "A game can have at most {MAX_CARD_DEFINITIONS} kinds of cards"
+            ));
         }
 
-        let mut spawn_points = map.spawn_points.clone();
-        let (shuffled_spawn_points, _) =
-            spawn_points.partial_shuffle(&mut thread_rng(), player_count);
-
-        let players: Vec<Player> = shuffled_spawn_points
-            .iter()
-            .map(|sp| Player::new(*sp, again_count, &card_definitions, draw_cards))
-            .collect();
+        let settings = RoundSettings {
+            round_registers,
+            draw_cards,
+        };
+        let card_pack_size = again_count + card_definitions.iter().map(|c| c.count).sum::<usize>();
+        validate_settings(settings, card_pack_size)?;
 
+        // players are only created when the game starts, see `Game::begin`
         let state = Arc::new(RwLock::new(GameState {
-            status: GameStatusInfo::Programming,
-            players,
+            status: GameStatusInfo::Lobby,
+            players: Vec::new(),
             game: Weak::new(),
+            lobby: Some(Lobby::new(player_count)),
+            host: None,
             reboot_queue: Vec::new(),
             running_state: (0, RegisterMovePhase::Checkpoints),
+            hook_positions: Vec::new(),
+            laser_hits: Vec::new(),
+            round: 0,
+            winners: None,
+            store: ScriptStore::new(player_count),
         }));
 
         let mut engine = Engine::new();
-        engine.set_max_operations(20000);
+        let script_deadline = limit_engine(&mut engine);
         engine.register_global_module(exported_module!(game_api).into());
-        let log = Arc::new(Mutex::new(String::new()));
+        let log = Arc::new(GameLog::default());
         {
             let log = Arc::clone(&log);
-            engine.on_print(move |msg| log.lock().unwrap().push_str(msg));
+            engine.on_print(move |msg| log.push(LogLevel::Info, msg.to_owned()));
         }
         {
             let log = Arc::clone(&log);
-            engine.on_debug(move |msg, src, pos| {
-                log.lock()
-                    .unwrap()
-                    .push_str(&format!("{} @ {pos:?} > {msg}", src.unwrap()));
-            });
+            engine.on_debug(move |msg, _, pos| log.push(LogLevel::Debug, format!("{pos} > {msg}")));
         }
 
         let mut game = Game {
+            name,
             map,
-            cards: Vec::with_capacity(card_definitions.len()),
+            scripts: RwLock::default(),
             last_nobody_connected: Mutex::new(Some(Instant::now() + Duration::from_secs(60))),
             player_connections: repeat_with(|| RwLock::new(Weak::new()))
                 .take(player_count)
                 .collect(),
             engine: Arc::new(engine),
+            script_deadline,
             state,
-            running_guard: tokio::sync::Mutex::new(()),
+            running_guard: Arc::new(tokio::sync::Mutex::new(())),
             log,
-            round_registers,
-            draw_cards,
+            log_visibility,
+            settings: RwLock::new(settings),
             player_count,
-            card_pack_size: again_count + card_definitions.iter().map(|c| c.count).sum::<usize>(),
+            card_pack_size,
+            again_count,
+            card_definitions,
+            mode_script_source: mode_script,
+            chat: Mutex::default(),
+            chat_events,
+            pause: PauseGate::default(),
+            aborted: AtomicBool::new(false),
+            kicked_players: Mutex::default(),
         };
+        game.scripts = RwLock::new(game.compile_scripts(settings)?);
+
+        let game = Arc::new(game);
+        game.state.try_write().unwrap().game = Arc::downgrade(&game);
+        Ok(game)
+    }
+
+    pub fn settings(&self) -> RoundSettings {
+        *self.settings.read().unwrap()
+    }
 
+    /// Validates new settings and compiles the scripts for them, without changing the game yet
+    ///
+    /// Compiling takes a while, so this shouldn't be called with the game state locked
+    pub fn prepare_settings(&self, settings: RoundSettings) -> Result<Scripts, String> {
+        validate_settings(settings, self.card_pack_size)?;
+        self.compile_scripts(settings)
+    }
+
+    /// Changes the settings to ones from [`Game::prepare_settings`], must only be called in the lobby
+    pub fn set_settings(&self, settings: RoundSettings, scripts: Scripts) {
+        *self.settings.write().unwrap() = settings;
+        *self.scripts.write().unwrap() = scripts;
+    }
+
+    /// Leaves the lobby: assigns random spawn points and deals the first hands
+    pub fn begin(&self, state: &mut GameState) {
+        let draw_cards = self.settings().draw_cards;
+        let mut spawn_points = self.map.spawn_points.clone();
+        let (shuffled_spawn_points, _) =
+            spawn_points.partial_shuffle(&mut thread_rng(), self.player_count);
+        state.players = shuffled_spawn_points
+            .iter()
+            .map(|sp| Player::new(*sp, self.again_count, &self.card_definitions, draw_cards))
+            .collect();
+        state.hook_positions = shuffled_spawn_points.iter().map(|sp| sp.0).collect();
+        state.lobby = None;
+        state.status = GameStatusInfo::Programming;
+    }
+
+    /// Current state of the game, waits for the round to finish if it's being evaluated
+    ///
+    /// A paused round could take long to finish, so saving fails instead
+    pub async fn savefile(&self) -> Result<Savefile, String> {
+        let _guard = if self.pause.is_paused() {
+            (self.running_guard.try_lock())
+                .map_err(|_| "The round is paused, resume the game to save it".to_owned())?
+        } else {
+            self.running_guard.lock().await
+        };
+        let state = self.state.read().unwrap();
+        if state.lobby.is_some() {
+            return Err("The game hasn't started yet".to_owned());
+        }
+        let settings = self.settings();
+        Ok(Savefile {
+            map: self.map.clone(),
+            again_count: self.again_count,
+            card_definitions: self.card_definitions.clone(),
+            round_registers: settings.round_registers,
+            draw_cards: settings.draw_cards,
+            log_visibility: self.log_visibility,
+            mode_script: self.mode_script_source.clone(),
+            round: state.round,
+            players: state.players.clone(),
+            store: state.store.clone(),
+            chat_events: self.chat_events,
+        })
+    }
+
+    /// Compiles the cards, map and mode scripts with the given settings
+    fn compile_scripts(&self, settings: RoundSettings) -> Result<Scripts, String> {
+        let mut scripts = Scripts::default();
         for CardInitializationDefinition {
             asset,
             code,
             count: _,
             name: card_name,
-        } in card_definitions
+        } in &self.card_definitions
         {
-            let scope = game.create_scope();
-            let mut ast = game
-                .engine
-                .compile_with_scope(&scope, code)
+            let (ast, scope) = self
+                .compile_card(code, card_name.clone(), settings)
                 .map_err(|e| format!("Error compiling script for card {card_name}: {e}"))?;
-            ast.set_source(card_name);
-            game.cards.push((asset, Arc::new(ast), Mutex::new(scope)));
+            scripts
+                .cards
+                .push((asset.clone(), Arc::new(ast), Mutex::new(scope)));
+        }
+        if let Some(script) = &self.map.script {
+            scripts.map = Some(self.compile_hooks_script(script, "map", settings)?);
+        }
+        if let Some(script) = &self.mode_script_source {
+            scripts.mode = Some(self.compile_hooks_script(script, "mode", settings)?);
         }
+        Ok(scripts)
+    }
 
-        let game = Arc::new(game);
-        game.state.try_write().unwrap().game = Arc::downgrade(&game);
-        Ok(game)
+    /// Compiles the script of a card, in the same scope it will be executed in
+    pub fn compile_card(
+        &self,
+        code: &str,
+        card_name: String,
+        settings: RoundSettings,
+    ) -> Result<(AST, Scope<'static>), rhai::ParseError> {
+        let scope = self.create_scope(settings);
+        let mut ast = self.engine.compile_with_scope(&scope, code)?;
+        ast.set_source(card_name);
+        Ok((ast, scope))
+    }
+
+    /// Compiles the map or mode script
+    fn compile_hooks_script(
+        &self,
+        code: &str,
+        source: &str,
+        settings: RoundSettings,
+    ) -> Result<(AST, Mutex<Scope<'static>>), String> {
+        let scope = self.create_scope(settings);
+        let mut ast = self
+            .engine
+            .compile_with_scope(&scope, code)
+            .map_err(|e| format!("Error compiling {source} script: {e}"))?;
+        ast.set_source(source);
+        Ok((ast, Mutex::new(scope)))
+    }
+
+    /// Name of the card as seen by card scripts
+    pub fn card_name(&self, card: Card) -> String {
+        match card {
+            Card::Again => "Again".to_owned(),
+            Card::SPAM => "SPAM".to_owned(),
+            Card::Custom(card_i) => self.scripts.read().unwrap().cards[card_i]
+                .1
+                .source()
+                .unwrap_or_default()
+                .to_owned(),
+        }
     }
 
-    fn create_scope(&self) -> Scope<'static> {
+    fn create_scope(&self, settings: RoundSettings) -> Scope<'static> {
         let mut scope = Scope::new();
         scope.push_constant("PLAYER_COUNT", self.player_count as i64);
-        scope.push_constant("ROUND_REGISTERS", self.round_registers as i64);
+        scope.push_constant("ROUND_REGISTERS", settings.round_registers as i64);
         scope.push_constant("MAP_WIDTH", i64::from(self.map.tiles.size().x));
         scope.push_constant("MAP_HEIGHT", i64::from(self.map.tiles.size().y));
         scope.push_constant("GAME", Arc::clone(&self.state));
@@ -178,12 +424,18 @@ impl Game {
             .iter()
             .map(|p| p.read().unwrap().upgrade())
             .collect::<Vec<_>>();
+        let (status, host) = {
+            let game_state = self.state.read().unwrap();
+            (game_state.status.clone(), game_state.host)
+        };
         let state = ServerMessage::GeneralState(GeneralState {
             player_names: player_connections
                 .iter()
                 .map(|conn_opt| conn_opt.as_ref().map(|conn| conn.player_name.clone()))
                 .collect(),
-            status: self.state.read().unwrap().status.clone(),
+            status,
+            host,
+            paused: self.pause.is_paused(),
         });
         for conn in player_connections.into_iter().flatten() {
             conn.sender
@@ -193,21 +445,47 @@ impl Game {
     }
 
     /// Handle when a player submits their programmed registers for given round
-    pub async fn program(&self, seat: usize, cards: Vec<Card>) -> Result<(), String> {
-        if cards.len() != self.round_registers {
-            return Err("Wrong number of cards".to_owned());
+    ///
+    /// If this was the last player, the round is started on a blocking thread, so that card scripts
+    /// don't hold up the async runtime. This returns without waiting for the round to finish.
+    pub async fn program(self: &Arc<Self>, seat: usize, cards: Vec<Card>) -> Result<(), GameError> {
+        if cards.len() != self.settings().round_registers {
+            return Err(GameError::new(
+                ErrorCode::InvalidProgram,
+                "Wrong number of cards",
+            ));
         }
 
-        let _guard = self.running_guard.lock().await;
+        let guard = Arc::clone(&self.running_guard).lock_owned().await;
 
         let mut state = self.state.write().unwrap();
+        if state.lobby.is_some() {
+            return Err(GameError::new(
+                ErrorCode::NotStarted,
+                "The game hasn't started yet",
+            ));
+        }
+        if state.winners.is_some() {
+            return Err(GameError::new(ErrorCode::GameOver, "The game is over"));
+        }
         state.players[seat].program(cards)?;
         state.send_programming_state_to_all();
 
         let should_run = state.players.iter().all(|p| p.prepared_cards.is_some());
         drop(state);
         if should_run {
-            self.run();
+            let game = Arc::clone(self);
+            tokio::task::spawn_blocking(move || {
+                let round = game.state.read().unwrap().round;
+                let started = Instant::now();
+                game.pause.take_waited();
+                game.run();
+                // a paused round would otherwise end up far outside of the histogram buckets
+                let duration = started.elapsed().saturating_sub(game.pause.take_waited());
+                METRICS.round_processed(duration);
+                info!(game = game.name.as_str(), round; "Round finished in {duration:?}");
+                drop(guard);
+            });
         }
         Ok(())
     }
@@ -245,41 +523,299 @@ impl Game {
                     execute_register_i -= 1;
                 }
                 SPAM => {
-                    player.prepared_cards.as_mut().unwrap()[execute_register_i] =
-                        player.draw_one_card();
+                    let Some(replacement) = player.draw_one_card() else {
+                        // all cards are in hands and registers, the SPAM does nothing
+                        break;
+                    };
+                    player.prepared_cards.as_mut().unwrap()[execute_register_i] = replacement;
                     // show the replaced card
                     state.send_animation_item(&[], true);
                     continue;
                 }
                 Custom(card_i) => {
-                    let ast = Arc::clone(&self.cards[card_i].1);
-                    let engine = Arc::clone(&self.engine);
                     drop(state);
-                    let res = engine.call_fn::<()>(
-                        &mut self.cards[card_i].2.lock().unwrap(),
-                        &ast,
-                        "execute",
-                        (player_i as i64, register_i as i64),
-                    );
-                    if let Err(e) = res {
-                        self.log.lock().unwrap().push_str(&format!(
-                            "Error running card {} on register {} for player {}: {}\n",
-                            ast.source().unwrap(),
-                            register_i + 1,
-                            player_i,
-                            e
-                        ));
+                    if let Err(e) = self.call_card(card_i, player_i, register_i) {
+                        let card_name = self.card_name(Custom(card_i));
+                        METRICS.script_error(&card_name);
+                        self.log.push_entry(LogEntry {
+                            level: LogLevel::Error,
+                            card: Some(card_name),
+                            player_i: Some(player_i),
+                            register_i: Some(register_i),
+                            message: describe_script_error(&e),
+                        });
                     }
+                    self.run_map_hooks();
                     break;
                 }
             }
         }
     }
 
+    /// Calls the `execute` function of a custom card
+    fn call_card(
+        &self,
+        card_i: usize,
+        player_i: usize,
+        register_i: usize,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        let context = ScriptContext {
+            card: Some(self.card_name(Card::Custom(card_i))),
+            player_i: Some(player_i),
+            register_i: Some(register_i),
+        };
+        let ast = Arc::clone(&self.scripts.read().unwrap().cards[card_i].1);
+        self.log.with_context(context, || {
+            self.script_deadline.run(|| {
+                self.engine.call_fn::<()>(
+                    &mut self.scripts.read().unwrap().cards[card_i].2.lock().unwrap(),
+                    &ast,
+                    "execute",
+                    (player_i as i64, register_i as i64),
+                )
+            })
+        })
+    }
+
+    /// Executes the first card of a [`NewGameData::sandbox`] game for given player, including map hooks
+    ///
+    /// Returns the states of all players before and after
+    pub fn dry_run_card(
+        &self,
+        player_i: usize,
+    ) -> (
+        Vec<PlayerPublicState>,
+        Vec<PlayerPublicState>,
+        Result<(), Box<rhai::EvalAltResult>>,
+    ) {
+        let before = {
+            let mut state = self.state.write().unwrap();
+            state.running_state = (0, RegisterMovePhase::PlayerCards);
+            for player in &mut state.players {
+                player.prepared_cards = Some(vec![Card::Custom(0)]);
+            }
+            state
+                .players
+                .iter()
+                .map(|p| p.public_state.clone())
+                .collect()
+        };
+        let res = self.call_card(0, player_i, 0);
+        self.run_map_hooks();
+        let after = self
+            .state
+            .read()
+            .unwrap()
+            .players
+            .iter()
+            .map(|p| p.public_state.clone())
+            .collect();
+        (before, after, res)
+    }
+
+    /// Calls a function from the map script, if the map has one and it defines the function
+    ///
+    /// Unlike with cards, any return value is ignored. Output of the hook is tagged with the player
+    /// it's called for, if any.
+    fn call_map_hook(&self, fn_name: &str, player_i: Option<usize>, args: impl FuncArgs) {
+        call_hook(
+            self,
+            self.scripts.read().unwrap().map.as_ref(),
+            fn_name,
+            player_i,
+            args,
+        );
+    }
+
+    /// Calls a function from the mode script, if the game has one and it defines the function
+    fn call_mode_hook(&self, fn_name: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        call_hook(
+            self,
+            self.scripts.read().unwrap().mode.as_ref(),
+            fn_name,
+            None,
+            args,
+        )
+    }
+
+    /// Players who won the game, if it's over
+    ///
+    /// Decided by `check_winner()` of the mode script if it defines it, which returns unit while
+    /// nobody has won, or the index or array of indices of the winners. Otherwise the first
+    /// players to visit all checkpoints win.
+    ///
+    /// Must be called without holding the state lock.
+    fn check_winner(&self) -> Option<Vec<usize>> {
+        let has_check_winner = self
+            .scripts
+            .read()
+            .unwrap()
+            .mode
+            .as_ref()
+            .is_some_and(|(ast, _)| ast.iter_functions().any(|f| f.name == "check_winner"));
+        if has_check_winner {
+            let res = self.call_mode_hook("check_winner", ())?;
+            return parse_winners(res, self.player_count).unwrap_or_else(|e| {
+                self.log.push(
+                    LogLevel::Error,
+                    format!("Invalid result of check_winner: {e}"),
+                );
+                None
+            });
+        }
+        let checkpoint_count = self.map.checkpoints.len();
+        if checkpoint_count == 0 {
+            return None;
+        }
+        let winners: Vec<usize> = (self.state.read().unwrap().players.iter().enumerate())
+            .filter(|(_, p)| p.public_state.checkpoint >= checkpoint_count)
+            .map(|(player_i, _)| player_i)
+            .collect();
+        (!winners.is_empty()).then_some(winners)
+    }
+
+    /// Ends the game if somebody won, returns whether it did
+    ///
+    /// Must be called without holding the state lock.
+    fn finish_if_won(&self) -> bool {
+        let Some(winners) = self.check_winner() else {
+            return false;
+        };
+        self.flush_log();
+        let mut state = self.state.write().unwrap();
+        state.status = GameStatusInfo::Finished(winners.clone());
+        state.winners = Some(winners);
+        drop(state);
+        self.send_general_state();
+        true
+    }
+
+    /// Runs `on_robot_enters_tile` for robots that moved to a different tile and `on_laser_hit`
+    /// for robots hit by a laser since the last call
+    ///
+    /// Repeats while the hooks themselves move robots around. Must be called without holding the state lock.
+    fn run_map_hooks(&self) {
+        for _ in 0..MAX_HOOK_ROUNDS {
+            let (entered, laser_hits) = {
+                let mut state = self.state.write().unwrap();
+                let positions: Vec<Position> = state
+                    .players
+                    .iter()
+                    .map(|p| p.public_state.position)
+                    .collect();
+                let entered: Vec<(usize, Position)> = positions
+                    .iter()
+                    .zip(&state.hook_positions)
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(player_i, (new, _))| (player_i, *new))
+                    .collect();
+                state.hook_positions = positions;
+                (entered, mem::take(&mut state.laser_hits))
+            };
+            if entered.is_empty() && laser_hits.is_empty() {
+                return;
+            }
+            for (player_i, pos) in entered {
+                self.call_map_hook(
+                    "on_robot_enters_tile",
+                    Some(player_i),
+                    (player_i as i64, pos),
+                );
+            }
+            for player_i in laser_hits {
+                self.call_map_hook("on_laser_hit", Some(player_i), (player_i as i64,));
+            }
+        }
+        self.log.push(
+            LogLevel::Error,
+            format!("Map hooks were still moving robots after {MAX_HOOK_ROUNDS} rounds, stopping"),
+        );
+    }
+
+    /// Runs hooks of custom tiles for all robots standing on them, then `on_end_of_register`
+    ///
+    /// Must be called without holding the state lock.
+    fn run_end_of_register_hooks(&self, register_i: usize) {
+        if self.scripts.read().unwrap().map.is_none() {
+            return;
+        }
+        let on_custom_tiles: Vec<(usize, u8)> = {
+            let state = self.state.read().unwrap();
+            state
+                .player_indices_by_priority()
+                .into_iter()
+                .filter(|player_i| !state.players[*player_i].public_state.is_rebooting)
+                .filter_map(|player_i| {
+                    match self
+                        .map
+                        .tiles
+                        .get(state.players[player_i].public_state.position)?
+                        .typ
+                    {
+                        TileType::Custom(custom_i) => Some((player_i, custom_i)),
+                        _ => None,
+                    }
+                })
+                .collect()
+        };
+        for (player_i, custom_i) in on_custom_tiles {
+            self.call_map_hook(
+                &format!("on_tile_{}", self.map.custom_tiles[custom_i as usize]),
+                Some(player_i),
+                (player_i as i64, register_i as i64),
+            );
+            self.run_map_hooks();
+        }
+        self.call_map_hook("on_end_of_register", None, (register_i as i64,));
+        self.run_map_hooks();
+    }
+
+    /// Makes the next animated state sent to each player a full one
+    fn reset_animation_deltas(&self) {
+        for conn_lock in &self.player_connections {
+            let Some(conn) = conn_lock.read().unwrap().upgrade() else {
+                continue;
+            };
+            *conn.last_animated_state.lock().unwrap() = None;
+        }
+    }
+
+    /// Sends everything logged so far to the players allowed to see it
+    fn flush_log(&self) {
+        let log = self.log.take();
+        if log.is_empty() {
+            return;
+        }
+        let host = self.state.read().unwrap().host;
+        for (seat, conn_lock) in self.player_connections.iter().enumerate() {
+            let Some(conn) = conn_lock.read().unwrap().upgrade() else {
+                continue;
+            };
+            let entries: Vec<LogEntry> = log
+                .iter()
+                .filter(|e| self.log_visibility.is_visible(e, seat, host))
+                .cloned()
+                .collect();
+            if !entries.is_empty() {
+                conn.sender
+                    .send(SocketMessage::SendMessage(ServerMessage::GameLog(entries)))
+                    .unwrap();
+            }
+        }
+    }
+
     fn run(&self) {
         use RegisterMovePhase::*;
+        let settings = self.settings();
         let mut state = self.state.write().unwrap();
-        for register_i in 0..self.round_registers {
+        for register_i in 0..settings.round_registers {
+            drop(state);
+            if !self.wait_while_paused() {
+                return;
+            }
+            state = self.state.write().unwrap();
+            self.reset_animation_deltas();
             for register_phase in RegisterMovePhase::ORDER {
                 state.running_state = (register_i, register_phase);
                 state.send_animation_item(&[], true);
@@ -302,19 +838,20 @@ impl Game {
                     Lasers => state.execute_lasers(),
                     Checkpoints => state.execute_checkpoints(),
                 }
-                let mut log = self.log.lock().unwrap();
-                if !log.is_empty() {
-                    let msg =
-                        SocketMessage::SendMessage(ServerMessage::GameLog(mem::take(&mut log)));
-                    for conn_lock in &self.player_connections {
-                        let Some(conn) = conn_lock.read().unwrap().upgrade() else {
-                            continue;
-                        };
-                        conn.sender.send(msg.clone()).unwrap();
-                    }
-                    *log = String::new();
-                }
+                drop(state);
+                self.run_map_hooks();
+                self.flush_log();
+                state = self.state.write().unwrap();
             }
+            drop(state);
+            self.run_end_of_register_hooks(register_i);
+            self.call_mode_hook("on_end_of_register", (register_i as i64,));
+            self.run_map_hooks();
+            self.flush_log();
+            if self.finish_if_won() {
+                return;
+            }
+            state = self.state.write().unwrap();
         }
 
         for player in &mut state.players {
@@ -322,12 +859,107 @@ impl Game {
                 .discard_pile
                 .append(&mut player.prepared_cards.take().unwrap());
             player.discard_pile.append(&mut player.hand);
-            player.hand = player.draw_n_cards(self.draw_cards);
+            player.hand = player.draw_n_cards(settings.draw_cards);
+            player.hand.append(&mut player.drawn_cards);
             player.public_state.is_rebooting = false;
         }
+        state.round += 1;
+        drop(state);
+        self.call_mode_hook("on_end_of_round", ());
+        self.run_map_hooks();
+        self.flush_log();
+        if self.finish_if_won() {
+            return;
+        }
+        state = self.state.write().unwrap();
         state.status = GameStatusInfo::Programming;
         state.send_programming_state_to_all();
         drop(state);
         self.send_general_state();
     }
 }
+
+fn validate_settings(
+    RoundSettings {
+        round_registers,
+        draw_cards,
+    }: RoundSettings,
+    card_pack_size: usize,
+) -> Result<(), String> {
+    if round_registers > draw_cards {
+        return Err("Too few cards to draw".to_owned());
+    }
+
+    if round_registers < 1 {
+        return Err("Too few registers per round".to_owned());
+    }
+
+    if card_pack_size <= draw_cards + 1 {
+        return Err("Too many cards to draw".to_owned());
+    }
+    Ok(())
+}
+
+/// Calls a function from a map or mode script, if there is the script and it defines the function
+///
+/// Returns `None` if the function isn't defined or fails, errors are logged
+fn call_hook(
+    game: &Game,
+    script: Option<&(AST, Mutex<Scope<'static>>)>,
+    fn_name: &str,
+    player_i: Option<usize>,
+    args: impl FuncArgs,
+) -> Option<Dynamic> {
+    let (ast, scope) = script?;
+    if !ast.iter_functions().any(|f| f.name == fn_name) {
+        return None;
+    }
+    let context = ScriptContext {
+        card: None,
+        player_i,
+        register_i: Some(game.state.read().unwrap().running_state.0),
+    };
+    game.log.with_context(context, || {
+        let res = game.script_deadline.run(|| {
+            game.engine
+                .call_fn::<Dynamic>(&mut scope.lock().unwrap(), ast, fn_name, args)
+        });
+        res.map_err(|e| {
+            game.log.push(
+                LogLevel::Error,
+                format!(
+                    "Error running {} hook {fn_name}: {}",
+                    ast.source().unwrap_or_default(),
+                    describe_script_error(&e)
+                ),
+            );
+        })
+        .ok()
+    })
+}
+
+/// See [`Game::check_winner`]
+fn parse_winners(value: Dynamic, player_count: usize) -> Result<Option<Vec<usize>>, String> {
+    if value.is_unit() {
+        return Ok(None);
+    }
+    let values = if value.is_array() {
+        value.into_array().unwrap()
+    } else {
+        vec![value]
+    };
+    let winners = values
+        .into_iter()
+        .map(|v| {
+            let type_name = v.type_name();
+            match v.try_cast::<i64>() {
+                Some(player_i) if (0..player_count as i64).contains(&player_i) => {
+                    Ok(player_i as usize)
+                }
+                Some(player_i) => Err(format!("There is no player {player_i}")),
+                None => Err(format!("Expected player index, got {type_name}")),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((!winners.is_empty()).then_some(winners))
+}
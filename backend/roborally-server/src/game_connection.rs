@@ -1,45 +1,116 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
 
 use futures::{stream::SplitSink, SinkExt, Stream, StreamExt};
 use roborally_structs::{
+    game_state::animated_state::RunningStateView,
     logging::{error, info, warn},
-    transport::{ClientMessage, ServerMessage},
+    transport::{
+        legacy::LegacyServerMessage, Capability, ClientMessage, ErrorCode, GameError, Hello,
+        ServerMessage, LEGACY_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION,
+    },
 };
+use serde::{Deserialize, Serialize};
 use tokio::{
     sync::mpsc::{unbounded_channel, UnboundedSender},
     time::timeout,
 };
 use warp::ws::{Message, WebSocket};
 
-use crate::game::Game;
+use crate::{chat::RateLimiter, config::config, game::Game, metrics::METRICS, Games};
+
+/// How long a new connection may take to send its [`ClientMessage::Hello`], clients that don't send
+/// one are then treated as [`LEGACY_PROTOCOL_VERSION`]
+///
+/// Current clients send it as soon as the socket opens, so this only has to cover the network
+/// latency - legacy clients don't see anything from the server until it runs out
+const HELLO_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub enum SocketMessage {
-    CloseWithNotice(String),
+    /// Closes the connection with the error's code as the close code and its message as the reason
+    CloseWithError(GameError),
     SendMessage(ServerMessage),
     Ping,
 }
 
-pub fn create_sender(mut sink: SplitSink<WebSocket, Message>) -> UnboundedSender<SocketMessage> {
+/// Encoding of messages sent to the client
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    /// Binary `rmp_serde` messages, used by the web client
+    #[default]
+    Msgpack,
+    /// Text messages with the same structure, for bots and other clients, see `GET /api/protocol-schema`
+    Json,
+}
+
+impl WireFormat {
+    fn encode_value(self, msg: &impl Serialize) -> Message {
+        match self {
+            Self::Msgpack => Message::binary(rmp_serde::to_vec(msg).unwrap()),
+            Self::Json => Message::text(serde_json::to_string(msg).unwrap()),
+        }
+    }
+
+    /// Returns `None` if the message can't be sent in the given protocol version
+    fn encode(self, msg: &ServerMessage, version: u32) -> Option<Message> {
+        if version == LEGACY_PROTOCOL_VERSION {
+            LegacyServerMessage::convert(msg).map(|m| self.encode_value(&m))
+        } else {
+            Some(self.encode_value(msg))
+        }
+    }
+}
+
+/// `version` is the protocol version agreed on in the [`Hello`] exchange
+///
+/// `game_name` and `seat` are only attached to log records, the seat is shared with the
+/// [`PlayerConnection`] so that it follows seat switches
+pub fn create_sender(
+    mut sink: SplitSink<WebSocket, Message>,
+    format: WireFormat,
+    version: u32,
+    game_name: String,
+    seat: Arc<AtomicUsize>,
+) -> UnboundedSender<SocketMessage> {
     let (sender, mut receiver) = unbounded_channel();
     tokio::task::spawn(async move {
         while let Some(msg) = receiver.recv().await {
+            let (game, seat) = (game_name.as_str(), seat.load(Ordering::Relaxed));
             match msg {
-                SocketMessage::CloseWithNotice(notice) => {
-                    info!("Closing connection with message: {}", &notice);
-                    if let Err(e) = sink.send(Message::close_with(1000_u16, notice)).await {
-                        warn!("Error when closing connection: {e}");
+                SocketMessage::CloseWithError(err) => {
+                    info!(game, seat; "Closing connection with message: {err}");
+                    let close = Message::close_with(err.code.close_code(), err.message);
+                    if let Err(e) = sink.send(close).await {
+                        METRICS.send_failed();
+                        warn!(game, seat; "Error when closing connection: {e}");
                     }
                 }
                 SocketMessage::SendMessage(m) => {
-                    if let Err(e) = sink
-                        .send(Message::binary(rmp_serde::to_vec(&m).unwrap()))
-                        .await
-                    {
-                        error!("Error sending message: {e}");
+                    let Some(encoded) = format.encode(&m, version) else {
+                        continue;
+                    };
+                    let bytes = encoded.as_bytes().len();
+                    match sink.send(encoded).await {
+                        Ok(()) => METRICS.message_sent(&m, bytes),
+                        Err(e) => {
+                            METRICS.send_failed();
+                            error!(game, seat; "Error sending message: {e}");
+                        }
+                    }
+                }
+                SocketMessage::Ping => {
+                    if let Err(e) = sink.send(Message::ping(Vec::new())).await {
+                        METRICS.send_failed();
+                        warn!(game, seat; "Error sending ping: {e}");
                     }
                 }
-                SocketMessage::Ping => sink.send(Message::ping(Vec::new())).await.unwrap(),
             }
         }
     });
@@ -49,34 +120,64 @@ pub fn create_sender(mut sink: SplitSink<WebSocket, Message>) -> UnboundedSender
 pub struct PlayerConnection {
     pub player_name: String,
     pub game: Arc<Game>,
-    pub seat: usize,
+    /// Can change while in the lobby, see [`PlayerConnection::seat`]
+    pub seat: Arc<AtomicUsize>,
     pub sender: UnboundedSender<SocketMessage>,
+    /// Last state sent to this player during a register, further states are sent as deltas to it
+    pub last_animated_state: Mutex<Option<RunningStateView>>,
+    /// Agreed on in the [`Hello`] exchange, [`LEGACY_PROTOCOL_VERSION`] if the client didn't send one
+    pub protocol_version: u32,
+    /// Agreed on in the [`Hello`] exchange
+    pub capabilities: Vec<Capability>,
+    /// Set when the host kicks the player, further messages are ignored until the connection closes
+    pub kicked: AtomicBool,
+    pub chat_rate_limit: RateLimiter,
+    /// All games of the server, so that the host can remove the game by aborting it
+    pub games: Games,
+}
+
+fn corrupted_message(e: impl std::fmt::Display) -> GameError {
+    GameError::new(
+        ErrorCode::CorruptedMessage,
+        format!("Received corrupted message: {e}"),
+    )
 }
 
 /// Attempts to receive a message
 ///
-/// If `Err(Some(String))` is returned, the associated writer should be closed with that message.
+/// Binary messages are decoded as msgpack and text messages as JSON, regardless of the [`WireFormat`]
+///
+/// If `Err(Some(GameError))` is returned, the associated writer should be closed with that error.
 ///
 /// If `Err(None)` is returned, the writer is already closed.
 ///
 /// In either `Err` case, this function shouldn't be called again for the same reader
 async fn receive_client_message<S: Stream<Item = Result<Message, warp::Error>> + Send + Unpin>(
     reader: &mut S,
-) -> Result<ClientMessage, Option<String>> {
+) -> Result<ClientMessage, Option<GameError>> {
     // this function would be cleaner using recursion, but with async function that requires boxing and can cause lifetime checker issues
     loop {
-        // even if the player doesn't make any action for 20 seconds, at least a `pong` should be received
-        let ws_msg = match timeout(Duration::from_secs(20), reader.next()).await {
+        // even if the player doesn't make any action until the ping timeout, at least a `pong` should be received
+        let ws_msg = match timeout(config().ping_timeout(), reader.next()).await {
             Ok(Some(Ok(x))) => x,
             // various network errors
-            Ok(Some(Err(e))) => return Err(Some(format!("Error receiving message: {e}"))),
+            Ok(Some(Err(e))) => {
+                return Err(Some(GameError::new(
+                    ErrorCode::ConnectionLost,
+                    format!("Error receiving message: {e}"),
+                )))
+            }
             // most likely: connection is already closed
             Ok(None) => return Err(None),
             // timeout
             Err(_) => {
-                return Err(Some(
-                    "No ping response from client for over 20 seconds".to_owned(),
-                ))
+                return Err(Some(GameError::new(
+                    ErrorCode::ConnectionLost,
+                    format!(
+                        "No ping response from client for over {} seconds",
+                        config().ping_timeout_secs
+                    ),
+                )))
             }
         };
         return {
@@ -86,54 +187,187 @@ async fn receive_client_message<S: Stream<Item = Result<Message, warp::Error>> +
                 // recursion
                 continue;
             } else if ws_msg.is_binary() {
-                match rmp_serde::from_slice(ws_msg.as_bytes()) {
-                    Ok(msg) => Ok(msg),
-                    Err(e) => Err(Some(format!("Received corrupted message: {e}"))),
-                }
+                rmp_serde::from_slice(ws_msg.as_bytes()).map_err(|e| Some(corrupted_message(e)))
+            } else if let Ok(text) = ws_msg.to_str() {
+                serde_json::from_str(text).map_err(|e| Some(corrupted_message(e)))
             } else {
-                Err(Some("Received corrupted message (unknown type)".to_owned()))
+                Err(Some(corrupted_message("unknown type")))
             }
         };
     }
 }
 
+/// Agrees on the protocol version and capabilities with the client, the result is the [`Hello`] to answer with
+///
+/// Clients newer than the server are answered with the server's version, it's up to them whether they can use it
+fn negotiate(client: &Hello) -> Result<Hello, GameError> {
+    let server = Hello::current();
+    if client.version < MIN_PROTOCOL_VERSION {
+        return Err(GameError::new(
+            ErrorCode::IncompatibleProtocol,
+            format!(
+                "Your client uses protocol version {}, but the server needs at least version {MIN_PROTOCOL_VERSION}. Try reloading the page.",
+                client.version
+            ),
+        ));
+    }
+    let capabilities = server
+        .capabilities
+        .into_iter()
+        .filter(|c| client.capabilities.contains(c))
+        .collect();
+    Ok(Hello {
+        version: client.version.min(server.version),
+        capabilities,
+    })
+}
+
+/// Waits for the [`ClientMessage::Hello`] that clients start with
+///
+/// Clients from before the handshake don't send one, they get [`Hello::legacy`] instead. If such a
+/// client sent some other message, it's returned as well, to be handled once the player is seated.
+///
+/// `Err(None)` means that the connection is already closed
+async fn receive_hello<S: Stream<Item = Result<Message, warp::Error>> + Send + Unpin>(
+    reader: &mut S,
+) -> Result<(Hello, Option<ClientMessage>), Option<GameError>> {
+    match timeout(HELLO_TIMEOUT, receive_client_message(reader)).await {
+        Ok(Ok(ClientMessage::Hello(hello))) => negotiate(&hello).map(|h| (h, None)).map_err(Some),
+        Ok(Ok(msg)) => Ok((Hello::legacy(), Some(msg))),
+        Err(_) => Ok((Hello::legacy(), None)),
+        Ok(Err(e)) => Err(e),
+    }
+}
+
 impl PlayerConnection {
+    pub fn seat(&self) -> usize {
+        self.seat.load(Ordering::Relaxed)
+    }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Whether the client is from before the handshake, and doesn't know the lobby and other newer messages
+    pub fn is_legacy(&self) -> bool {
+        self.protocol_version == LEGACY_PROTOCOL_VERSION
+    }
+
+    /// Sends an error without closing the connection, as a plain notice to clients that don't know error codes
+    pub fn send_error(&self, error: GameError) {
+        let msg = if self.has_capability(Capability::ErrorCodes) {
+            ServerMessage::Error(error)
+        } else {
+            ServerMessage::Notice(error.message)
+        };
+        self.sender.send(SocketMessage::SendMessage(msg)).unwrap();
+    }
+
+    /// Passes a game action from the client to the game, a repeated [`ClientMessage::Hello`] is ignored
+    async fn handle_action(self: &Arc<Self>, msg: ClientMessage) -> Result<(), GameError> {
+        let seat = self.seat();
+        match msg {
+            ClientMessage::Hello(_) => Ok(()),
+            ClientMessage::Program(cards) => self.game.program(seat, cards).await,
+            ClientMessage::SetReady(ready) => self.game.set_ready(seat, ready),
+            ClientMessage::SwitchSeat(to) => self.game.switch_seat(self, to),
+            ClientMessage::ChangeSettings(settings) => self.game.change_settings(seat, settings),
+            ClientMessage::Kick(target) => {
+                self.game.check_host(seat)?;
+                self.game.kick(target, "the host")
+            }
+            ClientMessage::StartGame => self.game.start(seat),
+            ClientMessage::Chat(text) => self.chat(&text),
+            ClientMessage::SetPaused(paused) => {
+                self.game.check_host(seat)?;
+                self.game.set_paused(paused);
+                Ok(())
+            }
+            ClientMessage::AbortGame => {
+                self.game.check_host(seat)?;
+                self.game.abort_and_remove(&self.games, "the host").await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Keeps pinging the client until the connection is dropped
+    fn start_ping_loop(self_weak: Weak<Self>) {
+        tokio::spawn(async move {
+            while let Some(ping_conn) = self_weak.upgrade() {
+                if let Err(e) = ping_conn.sender.send(SocketMessage::Ping) {
+                    warn!(game = ping_conn.game.name.as_str(), seat = ping_conn.seat(); "Error sending ping: {e}");
+                    break;
+                }
+                // free the Arc, only leave the Weak so that the seat is freed as soon as player disconnects
+                drop(ping_conn);
+                tokio::time::sleep(config().ping_interval()).await;
+            }
+        });
+    }
+
     /// Creates a player connections and starts receive loop
     ///
     /// The connection isn't returned - it lives in an `Arc` (reference-counted pointer), which is dropped when the receive loop ends
     ///
     /// Weak references to the `Arc` are stored in the game object, and in a ping keepalive loop
     pub async fn create_and_start(
+        games: Games,
         game_opt: Option<Arc<Game>>,
         socket: WebSocket,
+        game_name: String,
         player_name: String,
         seat: usize,
+        format: WireFormat,
     ) {
         use SocketMessage::*;
         let (w, mut reader) = socket.split();
-        let sender = create_sender(w);
+        let shared_seat = Arc::new(AtomicUsize::new(seat));
+        // nothing is sent before the protocol is agreed on, the client might not understand it
+        let hello_result = receive_hello(&mut reader).await;
+        let version = hello_result
+            .as_ref()
+            .map_or(LEGACY_PROTOCOL_VERSION, |(hello, _)| hello.version);
+        let sender = create_sender(w, format, version, game_name, Arc::clone(&shared_seat));
+        let (hello, first_message) = match hello_result {
+            Ok(x) => x,
+            Err(err_opt) => {
+                if let Some(e) = err_opt {
+                    sender.send(CloseWithError(e)).unwrap();
+                }
+                return;
+            }
+        };
         let Some(game) = game_opt else {
             sender
-                .send(CloseWithNotice(
-                    "Game with this ID doesn't exist".to_owned(),
-                ))
+                .send(CloseWithError(GameError::new(
+                    ErrorCode::GameNotFound,
+                    "Game with this ID doesn't exist",
+                )))
                 .unwrap();
             return;
         };
+        if let Err(e) = game.check_not_kicked(&player_name) {
+            sender.send(CloseWithError(e)).unwrap();
+            return;
+        }
 
         let self_arc = {
             let Some(player) = game.player_connections.get(seat) else {
                 sender
-                    .send(CloseWithNotice("There aren't that many seats".to_owned()))
+                    .send(CloseWithError(GameError::new(
+                        ErrorCode::SeatNotFound,
+                        "There aren't that many seats",
+                    )))
                     .unwrap();
                 return;
             };
             let mut guard = player.write().unwrap();
             if let Some(p) = guard.upgrade() {
                 sender
-                    .send(CloseWithNotice(format!(
-                        "{} is already connected to this seat",
-                        p.player_name
+                    .send(CloseWithError(GameError::new(
+                        ErrorCode::SeatTaken,
+                        format!("{} is already connected to this seat", p.player_name),
                     )))
                     .unwrap();
                 return;
@@ -142,58 +376,154 @@ impl PlayerConnection {
             let conn = Arc::new(Self {
                 player_name,
                 game: Arc::clone(&game),
-                seat,
+                seat: shared_seat,
                 sender,
+                last_animated_state: Mutex::new(None),
+                protocol_version: hello.version,
+                capabilities: hello.capabilities.clone(),
+                kicked: AtomicBool::new(false),
+                chat_rate_limit: RateLimiter::default(),
+                games,
             });
             *guard = Arc::downgrade(&conn);
             drop(guard);
-            game.state
-                .read()
-                .unwrap()
-                .send_programming_state_to_player(seat);
+            info!(game = game.name.as_str(), seat; "{} connected", conn.player_name);
+            conn.sender
+                .send(SendMessage(ServerMessage::Hello(hello)))
+                .unwrap();
+            game.player_joined(seat);
             game.send_general_state();
+            conn.send_chat_history();
+            game.post_chat_event(format!("{} connected", conn.player_name));
             conn
         };
 
-        let self_weak = Arc::downgrade(&self_arc);
-        // ping loop
-        tokio::spawn(async move {
-            while let Some(ping_conn) = self_weak.upgrade() {
-                if let Err(e) = ping_conn.sender.send(Ping) {
-                    warn!("Error sending ping: {e}");
-                    break;
-                }
-                // free the Arc, only leave the Weak so that the seat is freed as soon as player disconnects
-                drop(ping_conn);
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            }
-        });
+        Self::start_ping_loop(Arc::downgrade(&self_arc));
 
         // reader loop
         tokio::spawn(async move {
+            if let Some(msg) = first_message {
+                if let Err(e) = self_arc.handle_action(msg).await {
+                    self_arc.send_error(e);
+                }
+            }
             while let Some(msg) = match receive_client_message(&mut reader).await {
                 Err(err_opt) => {
                     if let Some(e) = err_opt {
-                        self_arc.sender.send(CloseWithNotice(e)).unwrap();
+                        self_arc.sender.send(CloseWithError(e)).unwrap();
                     }
                     None
                 }
                 Ok(msg) => Some(msg),
             } {
-                match msg {
-                    ClientMessage::Program(cards) => {
-                        let res = self_arc.game.program(self_arc.seat, cards).await;
-                        if let Err(e) = res {
-                            self_arc
-                                .sender
-                                .send(SocketMessage::SendMessage(ServerMessage::Notice(e)))
-                                .unwrap();
-                        }
-                    }
+                if self_arc.kicked.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Err(e) = self_arc.handle_action(msg).await {
+                    self_arc.send_error(e);
                 }
             }
-            info!("Ending receive loop for player {}", self_arc.player_name);
+            info!(
+                game = self_arc.game.name.as_str(), seat = self_arc.seat();
+                "Ending receive loop for player {}", self_arc.player_name
+            );
+            self_arc.game.player_left(&self_arc);
             self_arc.game.send_general_state();
+            (self_arc.game).post_chat_event(format!("{} left", self_arc.player_name));
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use roborally_structs::{card::Card, transport::PROTOCOL_VERSION};
+    use serde_json::json;
+
+    use super::*;
+
+    /// Runs [`receive_hello`] on a connection that sends the given JSON messages
+    async fn hello_from(
+        messages: Vec<serde_json::Value>,
+    ) -> Result<(Hello, Option<ClientMessage>), Option<GameError>> {
+        let messages = messages
+            .into_iter()
+            .map(|m| Ok(Message::text(m.to_string())));
+        let mut reader = stream::iter(messages).chain(stream::pending());
+        receive_hello(&mut reader).await
+    }
+
+    #[test]
+    fn current_version_is_accepted() {
+        let hello = negotiate(&Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: vec![Capability::Chat],
+        })
+        .unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert_eq!(hello.capabilities, vec![Capability::Chat]);
+    }
+
+    #[test]
+    fn newer_client_gets_server_version() {
+        let hello = negotiate(&Hello {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: Vec::new(),
+        })
+        .unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn previous_version_is_rejected() {
+        let err = negotiate(&Hello {
+            version: PROTOCOL_VERSION - 1,
+            capabilities: Vec::new(),
+        })
+        .unwrap_err();
+        assert_eq!(err.code, ErrorCode::IncompatibleProtocol);
+    }
+
+    #[tokio::test]
+    async fn hello_is_negotiated() {
+        let (hello, first) = hello_from(vec![json!({ "Hello": Hello::current() })])
+            .await
+            .unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert!(first.is_none());
+    }
+
+    #[tokio::test]
+    async fn client_without_hello_is_legacy() {
+        let (hello, first) = hello_from(vec![json!({ "Program": [Card::Again] })])
+            .await
+            .unwrap();
+        assert_eq!(hello.version, LEGACY_PROTOCOL_VERSION);
+        assert!(hello.capabilities.is_empty());
+        assert!(matches!(first, Some(ClientMessage::Program(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_client_waits_at_most_a_second() {
+        let started = tokio::time::Instant::now();
+        let (hello, first) = hello_from(Vec::new()).await.unwrap();
+        assert_eq!(hello.version, LEGACY_PROTOCOL_VERSION);
+        assert!(first.is_none());
+        assert!(started.elapsed() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn legacy_clients_only_get_known_messages() {
+        let notice = ServerMessage::Notice("hi".to_owned());
+        let hello = ServerMessage::Hello(Hello::current());
+        assert!(WireFormat::Msgpack
+            .encode(&notice, LEGACY_PROTOCOL_VERSION)
+            .is_some());
+        assert!(WireFormat::Msgpack
+            .encode(&hello, LEGACY_PROTOCOL_VERSION)
+            .is_none());
+        assert!(WireFormat::Msgpack
+            .encode(&hello, PROTOCOL_VERSION)
+            .is_some());
+    }
+}
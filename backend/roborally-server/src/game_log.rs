@@ -0,0 +1,124 @@
+use std::{mem, sync::Mutex};
+
+use roborally_structs::game_log::{LogEntry, LogLevel};
+use serde::{Deserialize, Serialize};
+
+/// Who gets to see what scripts print
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogVisibility {
+    #[default]
+    Everyone,
+    /// Only the player the script was running for, or the host if it wasn't running for anyone
+    Owner,
+    /// Only the host of the game
+    Host,
+}
+
+/// Card, player and register of the script that is currently running
+#[derive(Default, Clone)]
+pub struct ScriptContext {
+    pub card: Option<String>,
+    pub player_i: Option<usize>,
+    pub register_i: Option<usize>,
+}
+
+/// Log of everything that happened since it was last sent to the players
+#[derive(Default)]
+pub struct GameLog {
+    entries: Mutex<Vec<LogEntry>>,
+    context: Mutex<ScriptContext>,
+}
+
+impl GameLog {
+    /// Adds an entry tagged with the context of the running script
+    pub fn push(&self, level: LogLevel, message: String) {
+        let ScriptContext {
+            card,
+            player_i,
+            register_i,
+        } = self.context.lock().unwrap().clone();
+        self.push_entry(LogEntry {
+            level,
+            card,
+            player_i,
+            register_i,
+            message,
+        });
+    }
+
+    pub fn push_entry(&self, entry: LogEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Runs a script, tagging everything it logs with given context
+    pub fn with_context<T>(&self, context: ScriptContext, call: impl FnOnce() -> T) -> T {
+        *self.context.lock().unwrap() = context;
+        let res = call();
+        *self.context.lock().unwrap() = ScriptContext::default();
+        res
+    }
+
+    pub fn take(&self) -> Vec<LogEntry> {
+        mem::take(&mut *self.entries.lock().unwrap())
+    }
+}
+
+impl LogVisibility {
+    /// Whether the player in given seat should see the entry
+    ///
+    /// Output of scripts that weren't running for any player, like most hooks, has no owner, so
+    /// only the host sees it unless it's for everyone
+    pub const fn is_visible(self, entry: &LogEntry, seat: usize, host: Option<usize>) -> bool {
+        let is_host = matches!(host, Some(host) if host == seat);
+        match (self, entry.player_i) {
+            (Self::Everyone, _) => true,
+            (Self::Owner, Some(player_i)) => player_i == seat,
+            (Self::Owner, None) | (Self::Host, _) => is_host,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook_print(player_i: Option<usize>) -> LogEntry {
+        LogEntry {
+            level: LogLevel::Info,
+            card: None,
+            player_i,
+            register_i: None,
+            message: "hello".to_owned(),
+        }
+    }
+
+    /// Which of the seats 0, 1 and 2 see the entry, when seat 0 is the host
+    fn seeing_seats(visibility: LogVisibility, entry: &LogEntry) -> Vec<usize> {
+        (0..3)
+            .filter(|&seat| visibility.is_visible(entry, seat, Some(0)))
+            .collect()
+    }
+
+    #[test]
+    fn hook_print_for_everyone() {
+        let everyone = LogVisibility::Everyone;
+        assert_eq!(seeing_seats(everyone, &hook_print(None)), vec![0, 1, 2]);
+        assert_eq!(seeing_seats(everyone, &hook_print(Some(1))), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn hook_print_for_owner() {
+        let owner = LogVisibility::Owner;
+        assert_eq!(seeing_seats(owner, &hook_print(None)), vec![0]);
+        assert_eq!(seeing_seats(owner, &hook_print(Some(1))), vec![1]);
+    }
+
+    #[test]
+    fn hook_print_for_host() {
+        let host = LogVisibility::Host;
+        assert_eq!(seeing_seats(host, &hook_print(None)), vec![0]);
+        assert_eq!(seeing_seats(host, &hook_print(Some(1))), vec![0]);
+        assert!(!host.is_visible(&hook_print(None), 0, None));
+    }
+}
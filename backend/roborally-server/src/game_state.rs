@@ -2,18 +2,22 @@ use std::{collections::HashMap, fmt::Debug, future::Future, mem, sync::Weak};
 
 use roborally_structs::{
     animations::Animation,
+    game_log::LogLevel,
     game_state::{
-        animated_state::{AnimationItem, RunningStateView},
+        animated_state::{AnimationDelta, AnimationItem, RunningStateDelta, RunningStateView},
         phase::RegisterMovePhase,
         GameStatusInfo, ProgrammingState,
     },
     position::{ContinuousDirection, Direction, Position, Priority},
     tile::Tile,
     tile_type::TileType,
-    transport::ServerMessage,
+    transport::{Capability, ServerMessage},
 };
 
-use crate::{game::Game, game_connection::SocketMessage::SendMessage, player::Player};
+use crate::{
+    game::Game, game_connection::SocketMessage::SendMessage, lobby::Lobby, player::Player,
+    script_store::ScriptStore,
+};
 
 pub struct BoxedFuture(pub Box<dyn Future<Output = ()> + Send + Sync + Unpin + 'static>);
 
@@ -26,12 +30,27 @@ impl Debug for BoxedFuture {
 pub struct GameState {
     /// No logic should be tied to the status, it's purely presentational
     pub status: GameStatusInfo,
+    /// Empty until the game leaves the lobby
     pub players: Vec<Player>,
     pub game: Weak<Game>,
+    /// Set until the host starts the game
+    pub lobby: Option<Lobby>,
+    /// Seat of the player who can start the game and see script output with [`crate::game_log::LogVisibility::Host`]
+    pub host: Option<usize>,
     pub reboot_queue: Vec<usize>,
     /// It isn't great that this has to be here, but it would be too messy to pass this all over the place.
     /// Conversion into `PlayerGameStateView` needs to have access to this.
     pub running_state: (usize, RegisterMovePhase),
+    /// Player positions when the map hooks last ran, to find out which robots entered a new tile
+    pub hook_positions: Vec<Position>,
+    /// Players hit by a laser since the map hooks last ran
+    pub laser_hits: Vec<usize>,
+    /// Values stored by scripts
+    pub store: ScriptStore,
+    /// Number of rounds finished so far
+    pub round: usize,
+    /// Set once the game is over
+    pub winners: Option<Vec<usize>>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -82,26 +101,49 @@ impl GameState {
             let Some(conn) = conn_lock.read().unwrap().upgrade() else {
                 continue;
             };
-            let state = ServerMessage::AnimatedState(AnimationItem {
-                animations: animations.to_vec(),
-                state: include_state.then(|| RunningStateView {
-                    register: self.running_state.0,
-                    register_phase: self.running_state.1,
-                    my_cards: player.prepared_cards.as_ref().unwrap().clone(),
-                    players_revealed_cards: self
-                        .players
-                        .iter()
-                        .map(|p| {
-                            p.prepared_cards.as_ref().unwrap()[..=self.running_state.0].to_vec()
-                        })
-                        .collect(),
-                    player_states: self
-                        .players
-                        .iter()
-                        .map(|p| p.public_state.clone())
-                        .collect(),
+            let animations = animations.to_vec();
+            if !include_state {
+                let item = AnimationItem {
+                    animations,
+                    state: None,
+                };
+                conn.sender
+                    .send(SendMessage(ServerMessage::AnimatedState(item)))
+                    .unwrap();
+                continue;
+            }
+            let view = RunningStateView {
+                register: self.running_state.0,
+                register_phase: self.running_state.1,
+                my_cards: player.prepared_cards.as_ref().unwrap().clone(),
+                players_revealed_cards: self
+                    .players
+                    .iter()
+                    .map(|p| p.prepared_cards.as_ref().unwrap()[..=self.running_state.0].to_vec())
+                    .collect(),
+                player_states: self
+                    .players
+                    .iter()
+                    .map(|p| p.public_state.clone())
+                    .collect(),
+            };
+            let previous = conn
+                .last_animated_state
+                .lock()
+                .unwrap()
+                .replace(view.clone());
+            let state = match previous {
+                Some(previous) if conn.has_capability(Capability::AnimationDeltas) => {
+                    ServerMessage::AnimatedStateDelta(AnimationDelta {
+                        animations,
+                        state: RunningStateDelta::between(&previous, &view),
+                    })
+                }
+                _ => ServerMessage::AnimatedState(AnimationItem {
+                    animations,
+                    state: Some(view),
                 }),
-            });
+            };
             conn.sender.send(SendMessage(state)).unwrap();
         }
     }
@@ -129,7 +171,7 @@ impl GameState {
             } else {
                 "Recommendation violation: called player_at_position on a void tile, and there was more than 1 player there".to_owned()
             };
-            game.log.lock().unwrap().push_str(&message);
+            game.log.push(LogLevel::Error, message);
         }
         result
     }
@@ -291,9 +333,11 @@ impl GameState {
         let game = self.game.upgrade().unwrap();
         let reboot_token = game.map.reboot_token;
         for player_i in mem::take(&mut self.reboot_queue) {
+            game.post_chat_event(format!("{} rebooted", game.seat_name(player_i)));
             let player = &mut self.players[player_i];
             player.draw_spam();
             player.draw_spam();
+            player.reboot_count += 1;
             player.public_state.direction = player
                 .public_state
                 .direction
@@ -487,9 +531,10 @@ impl GameState {
             let mut bullet_pos = *start_pos;
             let mut tile = map.tiles.get(bullet_pos).unwrap();
             'map_bullet_flight: loop {
-                for player in &mut self.players {
+                for (player_i, player) in self.players.iter_mut().enumerate() {
                     if player.public_state.position == bullet_pos {
                         player.draw_spam();
+                        self.laser_hits.push(player_i);
                         animations.push(Animation::BulletFlight {
                             from: *start_pos,
                             to: bullet_pos,
@@ -539,9 +584,10 @@ impl GameState {
                 if tile.walls.get(direction.rotated().rotated()) {
                     break;
                 }
-                for player2 in &mut self.players {
+                for (player2_i, player2) in self.players.iter_mut().enumerate() {
                     if player2.public_state.position == bullet_pos {
                         player2.draw_spam();
+                        self.laser_hits.push(player2_i);
                         animations.push(Animation::BulletFlight {
                             from: start_position,
                             to: bullet_pos,
@@ -559,16 +605,21 @@ impl GameState {
     }
 
     pub fn execute_checkpoints(&mut self) {
-        let map = &self.game.upgrade().unwrap().map;
+        let game = self.game.upgrade().unwrap();
         for player_i in self.player_indices_by_priority() {
             let player = &mut self.players[player_i];
             if player.public_state.is_rebooting {
                 continue;
             }
-            if map.checkpoints.get(player.public_state.checkpoint)
+            if game.map.checkpoints.get(player.public_state.checkpoint)
                 == Some(&player.public_state.position)
             {
                 player.public_state.checkpoint += 1;
+                game.post_chat_event(format!(
+                    "{} reached checkpoint {}",
+                    game.seat_name(player_i),
+                    player.public_state.checkpoint
+                ));
                 self.send_animation_item(&[Animation::CheckpointVisited { player_i }], true);
             }
         }
@@ -0,0 +1,265 @@
+use std::sync::{atomic::Ordering, Arc, Weak};
+
+use roborally_structs::{
+    game_state::{LobbyState, RoundSettings},
+    transport::{ErrorCode, GameError, ServerMessage},
+};
+
+use crate::{
+    game::Game,
+    game_connection::{PlayerConnection, SocketMessage},
+    game_state::GameState,
+};
+
+/// Readiness of the players before the game starts
+pub struct Lobby {
+    pub ready: Vec<bool>,
+}
+
+impl Lobby {
+    pub fn new(player_count: usize) -> Self {
+        Self {
+            ready: vec![false; player_count],
+        }
+    }
+}
+
+fn require_lobby(state: &mut GameState) -> Result<&mut Lobby, GameError> {
+    state.lobby.as_mut().ok_or_else(|| {
+        GameError::new(
+            ErrorCode::NotInLobby,
+            "This is only possible before the game starts",
+        )
+    })
+}
+
+pub fn require_host(state: &GameState, seat: usize) -> Result<(), GameError> {
+    if state.host == Some(seat) {
+        Ok(())
+    } else {
+        Err(GameError::new(
+            ErrorCode::NotHost,
+            "Only the host can do this",
+        ))
+    }
+}
+
+impl Game {
+    /// Clients from before the handshake don't know the lobby, so they can't host and count as ready
+    fn knows_lobby(&self, seat: usize) -> bool {
+        self.player_connections[seat]
+            .read()
+            .unwrap()
+            .upgrade()
+            .is_some_and(|c| !c.is_legacy())
+    }
+
+    /// Sends the lobby to everyone connected, if the game hasn't started yet
+    pub fn send_lobby_state(&self) {
+        let (host, ready_players) = {
+            let state = self.state.read().unwrap();
+            let Some(lobby) = &state.lobby else {
+                return;
+            };
+            (state.host, lobby.ready.clone())
+        };
+        let settings = self.settings();
+        for (seat, conn_lock) in self.player_connections.iter().enumerate() {
+            let Some(conn) = conn_lock.read().unwrap().upgrade() else {
+                continue;
+            };
+            let msg = ServerMessage::Lobby(LobbyState {
+                seat,
+                host,
+                ready_players: ready_players.clone(),
+                settings,
+            });
+            conn.sender.send(SocketMessage::SendMessage(msg)).unwrap();
+        }
+    }
+
+    /// Starts the game if nobody connected can start it, like it did before the lobby existed
+    ///
+    /// A player that is just leaving the `leaving` seat doesn't count as connected. Returns whether
+    /// the game was started.
+    fn start_without_host(&self, state: &mut GameState, leaving: Option<usize>) -> bool {
+        if state.lobby.is_none() || state.host.is_some() {
+            return false;
+        }
+        let anyone_connected = (self.player_connections.iter().enumerate())
+            .any(|(s, c)| Some(s) != leaving && c.read().unwrap().upgrade().is_some());
+        if anyone_connected {
+            self.begin(state);
+            state.send_programming_state_to_all();
+        }
+        anyone_connected
+    }
+
+    /// Makes a newly connected player the host if there's none, and sends them the lobby or their cards
+    ///
+    /// A table of only clients that don't know the lobby is started right away
+    pub fn player_joined(&self, seat: usize) {
+        let mut state = self.state.write().unwrap();
+        if state.host.is_none() && self.knows_lobby(seat) {
+            state.host = Some(seat);
+        }
+        if self.start_without_host(&mut state, None) {
+            return;
+        }
+        if state.lobby.is_some() {
+            drop(state);
+            self.send_lobby_state();
+        } else {
+            state.send_programming_state_to_player(seat);
+        }
+    }
+
+    /// Frees the player's seat, see [`Game::seat_freed`]
+    pub fn player_left(&self, conn: &Arc<PlayerConnection>) {
+        let seat = conn.seat();
+        // the seat was already freed and might be taken by someone else, if the player was kicked
+        let seat_conn = self.player_connections[seat].read().unwrap().upgrade();
+        if !seat_conn.is_some_and(|c| Arc::ptr_eq(&c, conn)) {
+            return;
+        }
+        self.seat_freed(seat);
+    }
+
+    /// Makes the seat not ready, and passes the host to the player in the lowest seat if needed
+    ///
+    /// If only clients that don't know the lobby stay, the game is started
+    pub fn seat_freed(&self, seat: usize) {
+        let mut state = self.state.write().unwrap();
+        if let Some(lobby) = &mut state.lobby {
+            lobby.ready[seat] = false;
+        }
+        if state.host == Some(seat) {
+            state.host =
+                (0..self.player_connections.len()).find(|&s| s != seat && self.knows_lobby(s));
+        }
+        if self.start_without_host(&mut state, Some(seat)) {
+            return;
+        }
+        drop(state);
+        self.send_lobby_state();
+    }
+
+    pub fn set_ready(&self, seat: usize, ready: bool) -> Result<(), GameError> {
+        require_lobby(&mut self.state.write().unwrap())?.ready[seat] = ready;
+        self.send_lobby_state();
+        Ok(())
+    }
+
+    /// Moves the player to a free seat
+    pub fn switch_seat(&self, conn: &Arc<PlayerConnection>, to: usize) -> Result<(), GameError> {
+        let from = conn.seat();
+        {
+            let mut state = self.state.write().unwrap();
+            let lobby = require_lobby(&mut state)?;
+            let Some(target) = self.player_connections.get(to) else {
+                return Err(GameError::new(
+                    ErrorCode::SeatNotFound,
+                    "There aren't that many seats",
+                ));
+            };
+            if from == to {
+                return Ok(());
+            }
+            let mut target = target.write().unwrap();
+            if let Some(other) = target.upgrade() {
+                return Err(GameError::new(
+                    ErrorCode::SeatTaken,
+                    format!("{} is already connected to this seat", other.player_name),
+                ));
+            }
+            *target = Arc::downgrade(conn);
+            drop(target);
+            *self.player_connections[from].write().unwrap() = Weak::new();
+            conn.seat.store(to, Ordering::Relaxed);
+            lobby.ready.swap(from, to);
+            if state.host == Some(from) {
+                state.host = Some(to);
+            }
+        }
+        self.send_lobby_state();
+        self.send_general_state();
+        Ok(())
+    }
+
+    /// Changes the settings, everybody has to get ready again
+    ///
+    /// The scripts are compiled for the new settings before locking the game, the checks are then
+    /// repeated in case the game started or the host changed meanwhile
+    pub fn change_settings(&self, seat: usize, settings: RoundSettings) -> Result<(), GameError> {
+        require_host(&self.state.read().unwrap(), seat)?;
+        let scripts = self
+            .prepare_settings(settings)
+            .map_err(|e| GameError::new(ErrorCode::InvalidSettings, e))?;
+        let mut state = self.state.write().unwrap();
+        require_host(&state, seat)?;
+        let lobby = require_lobby(&mut state)?;
+        self.set_settings(settings, scripts);
+        lobby.ready.fill(false);
+        drop(state);
+        self.send_lobby_state();
+        Ok(())
+    }
+
+    /// Starts the game, once all seats are taken and everybody except the host is ready
+    ///
+    /// Clients that don't know the lobby are counted as ready
+    pub fn start(&self, seat: usize) -> Result<(), GameError> {
+        let mut state = self.state.write().unwrap();
+        require_host(&state, seat)?;
+        let lobby = require_lobby(&mut state)?;
+        let all_seated =
+            (self.player_connections.iter()).all(|c| c.read().unwrap().upgrade().is_some());
+        if !all_seated {
+            return Err(GameError::new(
+                ErrorCode::NotReady,
+                "All seats have to be taken",
+            ));
+        }
+        let all_ready = (lobby.ready.iter().enumerate())
+            .all(|(s, ready)| *ready || s == seat || !self.knows_lobby(s));
+        if !all_ready {
+            return Err(GameError::new(
+                ErrorCode::NotReady,
+                "Not everybody is ready",
+            ));
+        }
+        self.begin(&mut state);
+        state.send_programming_state_to_all();
+        drop(state);
+        self.send_general_state();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roborally_structs::transport::{LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+    use crate::moderation::tests::{connect_client, game};
+
+    #[test]
+    fn legacy_only_table_starts_right_away() {
+        let game = game();
+        let (_legacy, _legacy_rx) = connect_client(&game, 0, "legacy", LEGACY_PROTOCOL_VERSION);
+        let state = game.state.read().unwrap();
+        assert!(state.lobby.is_none());
+        assert_eq!(state.host, None);
+    }
+
+    #[test]
+    fn game_starts_when_the_last_host_leaves_legacy_players() {
+        let game = game();
+        let (host, _host_rx) = connect_client(&game, 0, "host", PROTOCOL_VERSION);
+        let (_legacy, _legacy_rx) = connect_client(&game, 1, "legacy", LEGACY_PROTOCOL_VERSION);
+        assert!(game.state.read().unwrap().lobby.is_some());
+        assert_eq!(game.state.read().unwrap().host, Some(0));
+
+        game.player_left(&host);
+        assert!(game.state.read().unwrap().lobby.is_none());
+    }
+}
@@ -32,40 +32,68 @@
 #![feature(let_chains)]
 #![feature(iter_intersperse)]
 
+mod card_sets;
+mod chat;
+mod config;
+mod course;
 mod game;
 mod game_connection;
+mod game_log;
 mod game_state;
+mod lobby;
+mod map_generator;
+mod maps;
+mod metrics;
+mod moderation;
 mod parser;
 mod player;
 mod rhai_api;
+mod savefile;
+mod script_limits;
+mod script_store;
+mod validation;
 
 use std::{
     collections::hash_map::{Entry, HashMap},
     fs,
-    io::Read,
-    mem,
-    str::FromStr,
     sync::Arc,
-    time::Duration,
 };
 
+use card_sets::CardSetRegistry;
+use clap::Parser;
+use config::{config, Cli, Command};
 use game::{Game, NewGameData};
-use game_connection::PlayerConnection;
+use game_connection::{PlayerConnection, WireFormat};
+use map_generator::generate_map;
+use maps::{MapRegistry, Maps};
 use roborally_structs::{
     game_map::GameMap,
-    logging::{self, info},
+    logging::{self, error, info},
+    transport::{ClientMessage, ErrorCode, GameError, ServerMessage},
 };
+use savefile::Savefile;
 use serde::{Deserialize, Serialize};
 use tokio::{select, sync::RwLock, time::Instant};
-use warp::{http::StatusCode, reply::with_status, Filter, Reply};
+use warp::{
+    http::StatusCode,
+    hyper::body::Bytes,
+    reply::{with_status, Response, WithStatus},
+    Filter, Reply,
+};
 
-use crate::{game_connection::SocketMessage, parser::Parse};
+use crate::{
+    game_connection::SocketMessage,
+    parser::Parse,
+    validation::{validate_cards, ValidateCardsData},
+};
 
 #[derive(Deserialize)]
 struct ConnectQuery {
     game_name: String,
     seat: usize,
     name: String,
+    #[serde(default)]
+    format: WireFormat,
 }
 
 async fn socket_connect_handler(
@@ -75,27 +103,65 @@ async fn socket_connect_handler(
 ) -> impl Reply {
     // It isn't possible to send an error response that can be reliably read in a browser during websocket handshake.
     // Therefore a connection is created even on invalid game_name, and the error is sent in Websocket close reason
-    let game = games_lock.read().await.get(&query.game_name).map(|g| {
-        *g.last_nobody_connected.lock().unwrap() = None;
-        Arc::clone(g)
-    });
+    let game = (games_lock.read().await.get(&query.game_name))
+        .filter(|g| !g.is_aborted())
+        .map(|g| {
+            *g.last_nobody_connected.lock().unwrap() = None;
+            Arc::clone(g)
+        });
     ws.on_upgrade(move |socket| {
-        PlayerConnection::create_and_start(game, socket, query.name, query.seat)
+        PlayerConnection::create_and_start(
+            games_lock,
+            game,
+            socket,
+            query.game_name,
+            query.name,
+            query.seat,
+            query.format,
+        )
     })
 }
 
-async fn new_game_handler(maps: Maps, games_lock: Games, mut data: NewGameData) -> impl Reply {
-    let game_name = mem::take(&mut data.name);
-    if game_name.len() > 50 {
+async fn new_game_handler(
+    maps: Maps,
+    card_sets: CardSets,
+    games_lock: Games,
+    mut data: NewGameData,
+) -> impl Reply {
+    let game_name = data.name.clone();
+    if game_name.len() > config().max_game_name_length {
         return with_status("Game name is too long".to_owned(), StatusCode::BAD_REQUEST);
     }
-    let Some(map) = maps.get(&data.map_name) else {
-        return with_status("Unknown map".to_owned(), StatusCode::BAD_REQUEST);
-    };
-    let game = match Game::new(map.clone(), data) {
-        Ok(g) => g,
-        Err(e) => return with_status(e, StatusCode::BAD_REQUEST),
+    if let Err(e) = data.resolve_card_set(&card_sets) {
+        return with_status(e, StatusCode::BAD_REQUEST);
+    }
+    let map = if let Some(params) = data.random_map.clone() {
+        // generating retries and repairs the map, which is too much work for the async runtime
+        let player_count = data.player_count;
+        match tokio::task::spawn_blocking(move || generate_map(&params, player_count)).await {
+            Ok(Ok(m)) => m,
+            Ok(Err(e)) => return with_status(e, StatusCode::BAD_REQUEST),
+            Err(e) => {
+                error!("Map generator failed: {e}");
+                return with_status(
+                    "Map generator failed".to_owned(),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        }
+    } else {
+        let Some(m) = maps.read().await.get(&data.map_name).cloned() else {
+            return with_status("Unknown map".to_owned(), StatusCode::BAD_REQUEST);
+        };
+        m
     };
+    match Game::new(map, data) {
+        Ok(game) => insert_game(&games_lock, game_name, game).await,
+        Err(e) => with_status(e, StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn insert_game(games_lock: &Games, game_name: String, game: Arc<Game>) -> WithStatus<String> {
     let mut games = games_lock.write().await;
     match games.entry(game_name) {
         Entry::Occupied(_) => with_status(
@@ -103,12 +169,61 @@ async fn new_game_handler(maps: Maps, games_lock: Games, mut data: NewGameData)
             StatusCode::BAD_REQUEST,
         ),
         Entry::Vacant(vacant) => {
+            info!(game = vacant.key().as_str(); "Game created");
             vacant.insert(game);
             with_status(String::new(), StatusCode::CREATED)
         }
     }
 }
 
+#[derive(Deserialize)]
+struct SavefileQuery {
+    game_name: String,
+}
+
+async fn get_savefile_handler(query: SavefileQuery, games_lock: Games) -> Response {
+    let Some(game) = games_lock.read().await.get(&query.game_name).cloned() else {
+        return with_status("Unknown game", StatusCode::NOT_FOUND).into_response();
+    };
+    match game.savefile().await {
+        Ok(savefile) => rmp_serde::to_vec(&savefile).unwrap().into_response(),
+        Err(e) => with_status(e, StatusCode::CONFLICT).into_response(),
+    }
+}
+
+/// Creates a new game from a savefile in the request body
+async fn load_savefile_handler(query: SavefileQuery, games_lock: Games, body: Bytes) -> impl Reply {
+    if query.game_name.len() > config().max_game_name_length {
+        return with_status("Game name is too long".to_owned(), StatusCode::BAD_REQUEST);
+    }
+    let savefile = match Savefile::decode(&body) {
+        Ok(s) => s,
+        Err(e) => return with_status(format!("Invalid savefile: {e}"), StatusCode::BAD_REQUEST),
+    };
+    match savefile.into_game(query.game_name.clone()) {
+        Ok(game) => insert_game(&games_lock, query.game_name, game).await,
+        Err(e) => with_status(e, StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_map_handler(query: GetMapQuery, maps: Maps, games_lock: Games) -> Box<dyn Reply> {
+    let registered_map = maps.read().await.get(&query.name).cloned();
+    let map = if registered_map.is_some() {
+        registered_map
+    } else {
+        // generated maps only live in the games that use them
+        games_lock
+            .read()
+            .await
+            .values()
+            .find_map(|g| (g.map.name == query.name).then(|| g.map.clone()))
+    };
+    map.map_or_else::<Box<dyn Reply>, _, _>(
+        || Box::new(with_status("Unknown map", StatusCode::NOT_FOUND)),
+        |m| Box::new(rmp_serde::to_vec(&m).unwrap()),
+    )
+}
+
 #[derive(Serialize)]
 struct GameListItem {
     seats: Vec<Option<String>>,
@@ -120,6 +235,15 @@ struct GameListItem {
     draw_cards: usize,
 }
 
+async fn metrics_handler(games_lock: Games) -> impl Reply {
+    let games = games_lock.read().await;
+    warp::reply::with_header(
+        metrics::render(games.values().map(AsRef::as_ref)),
+        "Content-Type",
+        "text/plain; version=0.0.4",
+    )
+}
+
 async fn list_games_handler(games_lock: Games) -> impl Reply {
     let mut games_list = Vec::new();
     games_lock.write().await.retain(|name, game| {
@@ -127,8 +251,10 @@ async fn list_games_handler(games_lock: Games) -> impl Reply {
             .last_nobody_connected
             .lock()
             .unwrap()
-            .is_some_and(|t| t.elapsed() > Duration::from_secs(300))
+            .is_some_and(|t| t.elapsed() > config().game_expiry())
         {
+            // also stops a paused round
+            game.abort("the server");
             return false;
         }
         let seats: Vec<Option<String>> = game
@@ -143,15 +269,10 @@ async fn list_games_handler(games_lock: Games) -> impl Reply {
             })
             .collect();
         if seats.iter().all(Option::is_none) {
-            let mut last_nobody_connected_guard = game.last_nobody_connected.lock().unwrap();
-            if let Some(last_nobody_connected) = *last_nobody_connected_guard {
-                if last_nobody_connected.elapsed() > Duration::from_secs(300) {
-                    return false;
-                }
-            } else {
-                *last_nobody_connected_guard = Some(Instant::now());
-            }
+            // the game expires once this is older than the expiry, see above
+            (game.last_nobody_connected.lock().unwrap()).get_or_insert_with(Instant::now);
         }
+        let settings = game.settings();
         games_list.push(GameListItem {
             seats,
             map_name: game.map.name.clone(),
@@ -162,14 +283,17 @@ async fn list_games_handler(games_lock: Games) -> impl Reply {
             ]
             .into_iter()
             .chain(
-                game.cards
+                game.scripts
+                    .read()
+                    .unwrap()
+                    .cards
                     .iter()
                     .map(|c| (c.0.clone(), c.1.source().unwrap().to_owned())),
             )
             .collect(),
             card_pack_size: game.card_pack_size,
-            round_registers: game.round_registers,
-            draw_cards: game.draw_cards,
+            round_registers: settings.round_registers,
+            draw_cards: settings.draw_cards,
         });
         true
     });
@@ -179,35 +303,203 @@ async fn list_games_handler(games_lock: Games) -> impl Reply {
 }
 
 type Games = Arc<RwLock<HashMap<String, Arc<Game>>>>;
-type Maps = Arc<HashMap<String, GameMap>>;
+/// Card sets are only loaded on startup, so they don't need a lock
+type CardSets = Arc<CardSetRegistry>;
 
 #[derive(Deserialize)]
 struct GetMapQuery {
     name: String,
 }
 
+#[derive(Deserialize)]
+struct UploadMapQuery {
+    /// Also save the map into the maps directory, so that it's available after restart
+    ///
+    /// Requires the admin token
+    #[serde(default)]
+    persist: bool,
+}
+
+/// Registers a map sent as text in the request body
+///
+/// On parsing failure, the structured [`parser::ParseError`] is returned as JSON
+async fn upload_map_handler(
+    query: UploadMapQuery,
+    maps: Maps,
+    is_admin: bool,
+    body: Bytes,
+) -> Response {
+    if query.persist && !is_admin {
+        return with_status(
+            "Saving maps requires the admin token",
+            StatusCode::FORBIDDEN,
+        )
+        .into_response();
+    }
+    let Ok(text) = std::str::from_utf8(&body) else {
+        return with_status("Map isn't valid UTF-8", StatusCode::BAD_REQUEST).into_response();
+    };
+    let map = match GameMap::parse(text, "map") {
+        Ok(m) => m,
+        Err(e) => {
+            return with_status(warp::reply::json(&e), StatusCode::BAD_REQUEST).into_response()
+        }
+    };
+    // the lock is held until the map is inserted, so that two uploads of the same map can't race
+    let mut maps_guard = maps.write().await;
+    if maps_guard.contains(&map.name) {
+        return with_status("Map with this name already exists", StatusCode::CONFLICT)
+            .into_response();
+    }
+    if !query.persist && maps_guard.uploaded_count() >= config().max_uploaded_maps {
+        return with_status(
+            "Too many maps were uploaded",
+            StatusCode::INSUFFICIENT_STORAGE,
+        )
+        .into_response();
+    }
+    // persisted maps are tracked as loaded from their file, so that the directory watcher picks up later edits
+    let mut file = None;
+    if query.persist {
+        // map name is validated by the parser to only contain [a-zA-Z0-9_-], so it's a safe filename
+        let path = config().maps_dir.join(&map.name);
+        if path.exists() {
+            return with_status(
+                "Map file with this name already exists",
+                StatusCode::CONFLICT,
+            )
+            .into_response();
+        }
+        let modified = fs::write(&path, text).and_then(|()| fs::metadata(&path)?.modified());
+        match modified {
+            Ok(modified) => file = Some((path, modified)),
+            Err(e) => {
+                error!("Error saving map {}: {e}", path.display());
+                return with_status("Error saving map", StatusCode::INTERNAL_SERVER_ERROR)
+                    .into_response();
+            }
+        }
+    }
+    info!("Registered uploaded map {}", map.name);
+    let name = map.name.clone();
+    maps_guard.insert(map, file);
+    drop(maps_guard);
+    with_status(name, StatusCode::CREATED).into_response()
+}
+
+/// Whether the request has `Authorization: Bearer <token>`, see [`config::Config::admin_token`].
+/// Without a token configured, nobody is an admin.
+fn is_admin(
+    token: Option<Arc<str>>,
+) -> impl Filter<Extract = (bool,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization").map(move |header: Option<String>| {
+        token.as_deref().is_some_and(|token| {
+            header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) == Some(token)
+        })
+    })
+}
+
+/// Passes only admin requests, see [`is_admin`], so admin endpoints look like they don't exist
+fn admin_auth(
+    token: Option<Arc<str>>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    is_admin(token)
+        .and_then(|authorized: bool| async move {
+            if authorized {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AdminAction {
+    Kick,
+    Pause,
+    Resume,
+    Abort,
+}
+
+#[derive(Deserialize)]
+struct AdminGameQuery {
+    game_name: String,
+    action: AdminAction,
+    /// Only for [`AdminAction::Kick`]
+    seat: Option<usize>,
+}
+
+/// Moderates any game, like its host can
+async fn admin_game_handler(query: AdminGameQuery, games_lock: Games) -> Response {
+    let Some(game) = games_lock.read().await.get(&query.game_name).cloned() else {
+        return with_status("Unknown game", StatusCode::NOT_FOUND).into_response();
+    };
+    match query.action {
+        AdminAction::Kick => {
+            let Some(seat) = query.seat else {
+                return with_status("Missing seat", StatusCode::BAD_REQUEST).into_response();
+            };
+            if let Err(e) = game.kick(seat, "an admin") {
+                return with_status(e.message, StatusCode::BAD_REQUEST).into_response();
+            }
+        }
+        AdminAction::Pause => game.set_paused(true),
+        AdminAction::Resume => game.set_paused(false),
+        AdminAction::Abort => {
+            game.abort_and_remove(&games_lock, "an admin").await;
+            info!(game = query.game_name.as_str(); "Game was aborted by an admin");
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Tries out cards on a sandbox game, so that their authors can see errors before creating a game
+async fn validate_cards_handler(maps: Maps, data: ValidateCardsData) -> Response {
+    let Some(map) = maps.read().await.get(&data.map_name).cloned() else {
+        return with_status("Unknown map", StatusCode::BAD_REQUEST).into_response();
+    };
+    // cards are executed, which mustn't block the async runtime
+    let validations =
+        tokio::task::spawn_blocking(move || validate_cards(&map, data.card_definitions)).await;
+    match validations {
+        Ok(Ok(validations)) => warp::reply::json(&validations).into_response(),
+        Ok(Err(e)) => with_status(e, StatusCode::BAD_REQUEST).into_response(),
+        Err(e) => {
+            error!("Card validation failed: {e}");
+            with_status("Card validation failed", StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
 #[tokio::main]
 #[allow(clippy::too_many_lines)]
 async fn main() {
-    logging::init();
+    let command = match Cli::parse().load_config() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    logging::init(config().log.clone(), config().log_format);
+    if let Some(Command::GenerateMap(args)) = command {
+        match map_generator::run_cli(&args) {
+            Ok(map) => print!("{map}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let config = config();
     let games_lock: Games = Games::default();
-    let maps: Maps = Arc::new(
-        fs::read_dir("maps")
-            .unwrap()
-            .map(|entry| {
-                let mut buffer = String::new();
-                fs::File::open(entry.unwrap().path())
-                    .unwrap()
-                    .read_to_string(&mut buffer)
-                    .unwrap();
-                let map = match GameMap::parse(&buffer, "") {
-                    Ok(map) => map,
-                    Err(e) => panic!("Error parsing map: {e}"),
-                };
-                (map.name.clone(), map)
-            })
-            .collect(),
-    );
+    let maps: Maps = Arc::new(RwLock::new(MapRegistry::load_dir(&config.maps_dir)));
+    tokio::spawn(maps::watch_dir(Arc::clone(&maps), config.maps_dir.clone()));
+    let card_sets: CardSets = Arc::new(CardSetRegistry::load_dir(&config.card_sets_dir));
+    let admin_token: Option<Arc<str>> = config.admin_token.as_deref().map(Into::into);
 
     // state is a allow-anything "filter" which clones the games Arc and passes it as a context
     let create_games_state = || {
@@ -220,6 +512,11 @@ async fn main() {
         warp::any().map(move || Arc::clone(&arc))
     };
 
+    let create_card_sets_state = || {
+        let arc = Arc::clone(&card_sets);
+        warp::any().map(move || Arc::clone(&arc))
+    };
+
     let api = warp::path("api");
     let list_games = api
         .and(warp::path("list-games").and(warp::path::end()))
@@ -231,8 +528,8 @@ async fn main() {
         .and(warp::path("list-maps").and(warp::path::end()))
         .and(warp::get())
         .and(create_maps_state())
-        .map(|maps: Maps| {
-            let mut maps_vec = maps.keys().collect::<Vec<_>>();
+        .then(|maps: Maps| async move {
+            let mut maps_vec = maps.read().await.names().cloned().collect::<Vec<_>>();
             maps_vec.sort();
             warp::reply::json(&maps_vec)
         });
@@ -242,19 +539,80 @@ async fn main() {
         .and(warp::query::<GetMapQuery>())
         .and(warp::get())
         .and(create_maps_state())
-        .map(|query: GetMapQuery, maps: Maps| {
-            maps.get(&query.name).map_or_else::<Box<dyn Reply>, _, _>(
-                || Box::new(with_status("Unknown map", StatusCode::NOT_FOUND)),
-                |map| Box::new(rmp_serde::to_vec(map).unwrap()),
-            )
-        });
+        .and(create_games_state())
+        .then(get_map_handler);
+    let map_errors = api
+        .and(warp::path!("admin" / "map-errors"))
+        .and(warp::get())
+        .and(admin_auth(admin_token.clone()))
+        .and(create_maps_state())
+        .then(|maps_lock: Maps| async move { warp::reply::json(maps_lock.read().await.errors()) });
+    let admin_game = api
+        .and(warp::path!("admin" / "game"))
+        .and(warp::post())
+        .and(admin_auth(admin_token.clone()))
+        .and(warp::query::<AdminGameQuery>())
+        .and(create_games_state())
+        .then(admin_game_handler);
+    let list_card_sets = api
+        .and(warp::path("list-card-sets").and(warp::path::end()))
+        .and(warp::get())
+        .and(create_card_sets_state())
+        .map(|sets: CardSets| warp::reply::json(&sets.list()));
+    let upload_map = api
+        .and(warp::path("maps").and(warp::path::end()))
+        .and(warp::post())
+        .and(warp::query::<UploadMapQuery>())
+        .and(create_maps_state())
+        .and(is_admin(admin_token.clone()))
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::bytes())
+        .then(upload_map_handler);
     let new_game = api
         .and(warp::path("new-game").and(warp::path::end()))
         .and(warp::post())
         .and(create_maps_state())
+        .and(create_card_sets_state())
         .and(create_games_state())
         .and(warp::body::json::<NewGameData>())
         .then(new_game_handler);
+    let validate_cards = api
+        .and(warp::path("validate-cards").and(warp::path::end()))
+        .and(warp::post())
+        .and(create_maps_state())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<ValidateCardsData>())
+        .then(validate_cards_handler);
+    let get_savefile = api
+        .and(warp::path("savefile").and(warp::path::end()))
+        .and(admin_auth(admin_token.clone()))
+        .and(warp::get())
+        .and(warp::query::<SavefileQuery>())
+        .and(create_games_state())
+        .then(get_savefile_handler);
+    let load_savefile = api
+        .and(warp::path("savefile").and(warp::path::end()))
+        .and(admin_auth(admin_token.clone()))
+        .and(warp::post())
+        .and(warp::query::<SavefileQuery>())
+        .and(create_games_state())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::bytes())
+        .then(load_savefile_handler);
+    let protocol_schema = api
+        .and(warp::path("protocol-schema").and(warp::path::end()))
+        .and(warp::get())
+        .map(|| {
+            warp::reply::json(&serde_json::json!({
+                "server_message": schemars::schema_for!(ServerMessage),
+                "client_message": schemars::schema_for!(ClientMessage),
+            }))
+        });
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(create_games_state())
+        .then(metrics_handler);
     let socket = warp::path("websocket")
         .and(warp::path("game").and(warp::path::end()))
         .and(warp::query::<ConnectQuery>())
@@ -262,18 +620,24 @@ async fn main() {
         .and(create_games_state())
         .then(socket_connect_handler);
 
-    let static_files = warp::fs::dir("www");
+    let static_files = warp::fs::dir(config.www_dir.clone());
 
     let routes = list_games
         .or(list_maps)
+        .or(list_card_sets)
         .or(get_map)
+        .or(upload_map)
+        .or(map_errors)
+        .or(admin_game)
         .or(new_game)
+        .or(validate_cards)
+        .or(get_savefile)
+        .or(load_savefile)
+        .or(protocol_schema)
+        .or(metrics)
         .or(socket)
         .or(static_files);
-    let ip_port = std::env::var("PORT")
-        .ok()
-        .and_then(|p| u16::from_str(&p).ok())
-        .map_or(([127, 0, 0, 1], 8080), |p| ([0, 0, 0, 0], p));
+    let ip_port = config.bind_address();
     let server = warp::serve(routes);
     #[cfg(unix)]
     let mut term =
@@ -298,9 +662,10 @@ async fn main() {
                 for player in &game.player_connections {
                     if let Some(conn) = player.read().unwrap().upgrade() {
                         conn.sender
-                            .send(SocketMessage::CloseWithNotice(
-                                "Server is shutting down. Sorry :(".to_owned(),
-                            ))
+                            .send(SocketMessage::CloseWithError(GameError::new(
+                                ErrorCode::ServerShutdown,
+                                "Server is shutting down. Sorry :(",
+                            )))
                             .unwrap();
                     }
                 }
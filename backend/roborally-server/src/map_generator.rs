@@ -0,0 +1,478 @@
+use std::collections::{HashSet, VecDeque};
+
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, Rng, SeedableRng};
+use roborally_structs::{
+    game_map::GameMap,
+    position::{Direction, Position},
+    tile::{DirectionBools, Grid, Tile},
+    tile_type::TileType,
+};
+use serde::Deserialize;
+
+use crate::parser::{Parse, Unparse};
+
+/// How many random maps are generated before giving up on the given parameters
+const MAX_ATTEMPTS: usize = 200;
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+/// Probability of each tile type (or feature) appearing on a generic tile of the map. Tiles not
+/// picked by any of these are floor.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct TileDensities {
+    pub void: f64,
+    pub belt: f64,
+    pub push_panel: f64,
+    pub rotation: f64,
+    /// Probability of a wall on each side of a tile
+    pub wall: f64,
+    /// Probability of a laser being mounted on a floor tile
+    pub laser: f64,
+}
+
+impl Default for TileDensities {
+    fn default() -> Self {
+        Self {
+            void: 0.08,
+            belt: 0.2,
+            push_panel: 0.03,
+            rotation: 0.03,
+            wall: 0.04,
+            laser: 0.02,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MapGeneratorParams {
+    pub width: i16,
+    pub height: i16,
+    pub checkpoints: usize,
+    #[serde(default)]
+    pub densities: TileDensities,
+    /// Random if not given
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Generates a random map that passes all parser validations, and where all checkpoints can be
+/// reached from all spawn points and from the reboot token
+///
+/// Candidates that don't satisfy this are repaired by clearing paths to the reboot token, see
+/// [`repair`], and only thrown away if even that fails.
+///
+/// The first column of the map is reserved as a "docking bay" for the spawn points and the antenna.
+pub fn generate_map(params: &MapGeneratorParams, player_count: usize) -> Result<GameMap, String> {
+    let size = Position {
+        x: params.width,
+        y: params.height,
+    };
+    if size.x < 4 || size.y < 4 {
+        return Err("Map must be at least 4x4".to_owned());
+    }
+    if size.x > 100 || size.y > 100 {
+        return Err("Map can be at most 100x100".to_owned());
+    }
+    if player_count < 1 || player_count >= size.y as usize {
+        return Err("The map must be higher than the number of players".to_owned());
+    }
+    // checkpoints, reboot token and the strip behind it must fit outside the docking bay
+    let needed_tiles = (params.checkpoints)
+        .checked_add(player_count)
+        .and_then(|n| n.checked_add(1));
+    if params.checkpoints < 1
+        || needed_tiles.is_none_or(|n| n > ((size.x - 1) * size.y) as usize / 2)
+    {
+        return Err("Invalid number of checkpoints for this map size".to_owned());
+    }
+    let densities = params.densities;
+    if [
+        densities.void,
+        densities.belt,
+        densities.push_panel,
+        densities.rotation,
+        densities.wall,
+        densities.laser,
+    ]
+    .iter()
+    .any(|d| !(0.0..=1.0).contains(d))
+        || densities.void + densities.belt + densities.push_panel + densities.rotation > 1.0
+    {
+        return Err("Tile densities must be in range 0..=1 and sum to at most 1".to_owned());
+    }
+
+    let seed = params.seed.unwrap_or_else(|| thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..MAX_ATTEMPTS {
+        let candidate = generate_candidate(&mut rng, size, player_count, params, seed);
+        // round-trip through the text format, so that exactly the same validations apply as for maps on disk
+        let Ok(mut map) = GameMap::parse(&candidate.unparse(), "generated map") else {
+            continue;
+        };
+        if !is_fully_reachable(&map) {
+            repair(&mut map);
+            let Ok(repaired) = GameMap::parse(&map.unparse(), "generated map") else {
+                continue;
+            };
+            map = repaired;
+        }
+        if is_fully_reachable(&map) {
+            return Ok(map);
+        }
+    }
+    Err(format!(
+        "Failed to generate a valid map in {MAX_ATTEMPTS} attempts, try lowering the densities"
+    ))
+}
+
+fn random_tile(rng: &mut StdRng, densities: &TileDensities) -> Tile {
+    let roll: f64 = rng.gen();
+    let typ = if roll < densities.void {
+        TileType::Void
+    } else if roll < densities.void + densities.belt {
+        TileType::Belt(rng.gen_bool(0.5), *DIRECTIONS.choose(rng).unwrap())
+    } else if roll < densities.void + densities.belt + densities.push_panel {
+        let divisor = rng.gen_range(1..=2);
+        TileType::PushPanel(
+            *DIRECTIONS.choose(rng).unwrap(),
+            divisor,
+            rng.gen_range(0..divisor),
+        )
+    } else if roll < densities.void + densities.belt + densities.push_panel + densities.rotation {
+        TileType::Rotation(rng.gen_bool(0.5))
+    } else {
+        TileType::Floor
+    };
+    let walls = DirectionBools {
+        up: rng.gen_bool(densities.wall),
+        right: rng.gen_bool(densities.wall),
+        down: rng.gen_bool(densities.wall),
+        left: rng.gen_bool(densities.wall),
+    };
+    Tile { typ, walls }
+}
+
+fn generate_candidate(
+    rng: &mut StdRng,
+    size: Position,
+    player_count: usize,
+    params: &MapGeneratorParams,
+    seed: u64,
+) -> GameMap {
+    let plain_floor = Tile {
+        typ: TileType::Floor,
+        walls: DirectionBools::default(),
+    };
+    let mut tiles = Grid::new(
+        (0..size.y)
+            .flat_map(|_| 0..size.x)
+            .map(|x| {
+                if x == 0 {
+                    plain_floor
+                } else {
+                    random_tile(rng, &params.densities)
+                }
+            })
+            .collect(),
+        size,
+    )
+    .unwrap();
+
+    // docking bay: spawn points and antenna in the first column
+    let mut bay_rows: Vec<i16> = (0..size.y).collect();
+    bay_rows.shuffle(rng);
+    let antenna = Position {
+        x: 0,
+        y: bay_rows.pop().unwrap(),
+    };
+    *tiles.get_mut(antenna).unwrap() = Tile {
+        typ: TileType::Floor,
+        walls: DirectionBools {
+            up: true,
+            right: true,
+            down: true,
+            left: true,
+        },
+    };
+    let mut spawn_rows = bay_rows[..player_count].to_vec();
+    spawn_rows.sort_unstable();
+    let spawn_points = spawn_rows
+        .into_iter()
+        .map(|y| (Position { x: 0, y }, Direction::Right))
+        .collect();
+
+    let mut free_positions: Vec<Position> = (0..size.y)
+        .flat_map(|y| (1..size.x).map(move |x| Position { x, y }))
+        .collect();
+    free_positions.shuffle(rng);
+    let mut used_positions = HashSet::new();
+
+    // reboot token, facing into a strip of floor long enough for all players
+    let reboot_token = loop {
+        let pos = free_positions.pop().unwrap();
+        let fitting_directions: Vec<Direction> = DIRECTIONS
+            .into_iter()
+            .filter(|dir| {
+                let end = (0..player_count).fold(pos, |p, _| p.moved_in_direction(*dir));
+                end.x >= 1 && size.contains(end)
+            })
+            .collect();
+        if let Some(dir) = fitting_directions.choose(rng) {
+            break (pos, *dir);
+        }
+    };
+    *tiles.get_mut(reboot_token.0).unwrap() = plain_floor;
+    used_positions.insert(reboot_token.0);
+    let mut strip_pos = reboot_token.0;
+    for _ in 0..player_count {
+        strip_pos = strip_pos.moved_in_direction(reboot_token.1);
+        let tile = tiles.get_mut(strip_pos).unwrap();
+        if tile.typ == TileType::Void {
+            tile.typ = TileType::Floor;
+        }
+        // rebooted robots are placed here, so no checkpoints
+        used_positions.insert(strip_pos);
+    }
+
+    let checkpoints = free_positions
+        .iter()
+        .rev()
+        .filter(|pos| !used_positions.contains(*pos))
+        .take(params.checkpoints)
+        .copied()
+        .collect::<Vec<_>>();
+    for pos in &checkpoints {
+        *tiles.get_mut(*pos).unwrap() = plain_floor;
+        used_positions.insert(*pos);
+    }
+
+    let lasers = random_lasers(rng, &tiles, &used_positions, params.densities.laser);
+
+    GameMap {
+        name: format!("RNG-{seed:x}"),
+        tiles,
+        antenna,
+        reboot_token,
+        checkpoints,
+        spawn_points,
+        lasers,
+        custom_tiles: Vec::new(),
+        script: None,
+    }
+}
+
+/// Lasers on floor tiles outside the docking bay that aren't used for anything else
+fn random_lasers(
+    rng: &mut StdRng,
+    tiles: &Grid<Tile>,
+    used_positions: &HashSet<Position>,
+    density: f64,
+) -> Vec<(Position, Direction)> {
+    let size = tiles.size();
+    let mut lasers = Vec::new();
+    for y in 0..size.y {
+        for x in 1..size.x {
+            let pos = Position { x, y };
+            if !used_positions.contains(&pos)
+                && tiles.get(pos).unwrap().typ == TileType::Floor
+                && rng.gen_bool(density)
+            {
+                lasers.push((pos, *DIRECTIONS.choose(rng).unwrap()));
+            }
+        }
+    }
+    lasers
+}
+
+/// Checks that every checkpoint can be reached from every spawn point and from the reboot token,
+/// moving only over non-void tiles and through sides without walls
+///
+/// This ignores belts and other moving elements - a robot can always move against them.
+fn is_fully_reachable(map: &GameMap) -> bool {
+    map.spawn_points
+        .iter()
+        .chain(std::iter::once(&map.reboot_token))
+        .all(|(start, _)| {
+            let reachable = reachable_from(map, *start);
+            map.checkpoints.iter().all(|cp| reachable.contains(cp))
+        })
+}
+
+/// Clears a path from every spawn point and checkpoint that can't reach the reboot token to it
+///
+/// Reachability goes both ways, so afterwards everything can reach everything. The paths go
+/// horizontally first, so they don't cross the docking bay.
+fn repair(map: &mut GameMap) {
+    let target = map.reboot_token.0;
+    let starts: Vec<Position> = (map.spawn_points.iter().map(|(pos, _)| *pos))
+        .chain(map.checkpoints.iter().copied())
+        .collect();
+    for start in starts {
+        if !reachable_from(map, target).contains(&start) {
+            clear_path(&mut map.tiles, start, target);
+        }
+    }
+}
+
+/// Removes walls and void along the path going horizontally from `from`, then vertically to `to`
+fn clear_path(tiles: &mut Grid<Tile>, from: Position, to: Position) {
+    let mut pos = from;
+    while pos != to {
+        let dir = if pos.x < to.x {
+            Direction::Right
+        } else if pos.x > to.x {
+            Direction::Left
+        } else if pos.y < to.y {
+            Direction::Down
+        } else {
+            Direction::Up
+        };
+        set_wall(&mut tiles.get_mut(pos).unwrap().walls, dir, false);
+        pos = pos.moved_in_direction(dir);
+        let tile = tiles.get_mut(pos).unwrap();
+        if tile.typ == TileType::Void {
+            tile.typ = TileType::Floor;
+        }
+        set_wall(&mut tile.walls, dir.rotated().rotated(), false);
+    }
+}
+
+const fn set_wall(walls: &mut DirectionBools, dir: Direction, value: bool) {
+    match dir {
+        Direction::Up => walls.up = value,
+        Direction::Right => walls.right = value,
+        Direction::Down => walls.down = value,
+        Direction::Left => walls.left = value,
+    }
+}
+
+fn reachable_from(map: &GameMap, start: Position) -> HashSet<Position> {
+    let mut visited = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(pos) = queue.pop_front() {
+        let tile = map.tiles.get(pos).unwrap();
+        for dir in DIRECTIONS {
+            let target = pos.moved_in_direction(dir);
+            if tile.walls.get(dir) || visited.contains(&target) {
+                continue;
+            }
+            let Some(target_tile) = map.tiles.get(target) else {
+                continue;
+            };
+            if target_tile.typ == TileType::Void || target_tile.walls.get(dir.rotated().rotated()) {
+                continue;
+            }
+            visited.insert(target);
+            queue.push_back(target);
+        }
+    }
+    visited
+}
+
+/// Options of `roborally-server generate-map`, densities not given are taken from [`TileDensities::default`]
+#[derive(clap::Args)]
+pub struct GenerateMapArgs {
+    #[arg(long, default_value_t = 12)]
+    width: i16,
+    #[arg(long, default_value_t = 12)]
+    height: i16,
+    #[arg(long, default_value_t = 4)]
+    players: usize,
+    #[arg(long, default_value_t = 3)]
+    checkpoints: usize,
+    /// Random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long)]
+    void: Option<f64>,
+    #[arg(long)]
+    belt: Option<f64>,
+    #[arg(long)]
+    push_panel: Option<f64>,
+    #[arg(long)]
+    rotation: Option<f64>,
+    #[arg(long)]
+    wall: Option<f64>,
+    #[arg(long)]
+    laser: Option<f64>,
+}
+
+/// Entrypoint for `roborally-server generate-map`, returns the map in the text format
+pub fn run_cli(args: &GenerateMapArgs) -> Result<String, String> {
+    let defaults = TileDensities::default();
+    let params = MapGeneratorParams {
+        width: args.width,
+        height: args.height,
+        checkpoints: args.checkpoints,
+        densities: TileDensities {
+            void: args.void.unwrap_or(defaults.void),
+            belt: args.belt.unwrap_or(defaults.belt),
+            push_panel: args.push_panel.unwrap_or(defaults.push_panel),
+            rotation: args.rotation.unwrap_or(defaults.rotation),
+            wall: args.wall.unwrap_or(defaults.wall),
+            laser: args.laser.unwrap_or(defaults.laser),
+        },
+        seed: args.seed,
+    };
+    generate_map(&params, args.players).map(|map| map.unparse())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(seed: u64) -> MapGeneratorParams {
+        MapGeneratorParams {
+            width: 10,
+            height: 8,
+            checkpoints: 4,
+            densities: TileDensities {
+                void: 0.4,
+                wall: 0.3,
+                ..TileDensities::default()
+            },
+            seed: Some(seed),
+        }
+    }
+
+    #[test]
+    fn generated_maps_are_reachable() {
+        for seed in 0..50 {
+            let map = generate_map(&params(seed), 3).unwrap();
+            assert!(is_fully_reachable(&map), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn checkpoints_are_off_the_reboot_strip() {
+        for seed in 0..50 {
+            let map = generate_map(&params(seed), 3).unwrap();
+            let (mut pos, dir) = map.reboot_token;
+            for _ in 0..=3 {
+                assert!(!map.checkpoints.contains(&pos), "seed {seed}");
+                pos = pos.moved_in_direction(dir);
+            }
+        }
+    }
+
+    #[test]
+    fn repair_connects_walled_off_checkpoint() {
+        let mut map = generate_map(&params(1), 3).unwrap();
+        let checkpoint = map.checkpoints[0];
+        map.tiles.get_mut(checkpoint).unwrap().walls = DirectionBools {
+            up: true,
+            right: true,
+            down: true,
+            left: true,
+        };
+        assert!(!is_fully_reachable(&map));
+        repair(&mut map);
+        assert!(is_fully_reachable(&map));
+    }
+}
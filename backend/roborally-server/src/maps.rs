@@ -0,0 +1,271 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use roborally_structs::{
+    game_map::GameMap,
+    logging::{error, info},
+};
+use tokio::sync::RwLock;
+
+use crate::{course::Course, parser::Parse};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+pub type Maps = Arc<RwLock<MapRegistry>>;
+
+/// All maps available for new games
+///
+/// Maps come either from files in the maps directory (these are reloaded when the file changes),
+/// or from uploads that weren't persisted.
+///
+/// Running games hold their own clone of the map, so nothing here affects them.
+#[derive(Default)]
+pub struct MapRegistry {
+    maps: HashMap<String, GameMap>,
+    /// Modification time of each known file in the maps directory, and name of the map loaded from it
+    ///
+    /// If the last load of a file failed, the previous version of the map stays loaded
+    files: HashMap<PathBuf, (SystemTime, Option<String>)>,
+    /// Course definitions from the maps directory, composed into maps whenever any file changes
+    courses: HashMap<PathBuf, Course>,
+    /// Last error for each file (or the directory itself) that failed to load
+    errors: BTreeMap<String, String>,
+    /// Maps uploaded without a file, they're only kept in memory
+    uploaded: usize,
+}
+
+/// Content of a file in the maps directory
+enum MapFile {
+    Map(Box<GameMap>),
+    Course(Course),
+}
+
+/// Result of checking one file in the maps directory
+enum FileChange {
+    Modified(PathBuf, SystemTime, Result<MapFile, String>),
+    Removed(PathBuf),
+}
+
+impl MapRegistry {
+    /// Creates the registry with all maps from the directory
+    pub fn load_dir(dir: &Path) -> Self {
+        let mut registry = Self::default();
+        registry.apply_scan(dir, scan_dir(dir, &HashMap::new()));
+        info!("Loaded {} maps", registry.maps.len());
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&GameMap> {
+        self.maps.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.maps.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.maps.keys()
+    }
+
+    pub const fn uploaded_count(&self) -> usize {
+        self.uploaded
+    }
+
+    pub const fn errors(&self) -> &BTreeMap<String, String> {
+        &self.errors
+    }
+
+    /// Registers a new map, optionally tracked as loaded from the given file
+    pub fn insert(&mut self, map: GameMap, file: Option<(PathBuf, SystemTime)>) {
+        if let Some((path, modified)) = file {
+            self.files.insert(path, (modified, Some(map.name.clone())));
+        } else {
+            self.uploaded += 1;
+        }
+        self.maps.insert(map.name.clone(), map);
+    }
+
+    fn file_versions(&self) -> HashMap<PathBuf, SystemTime> {
+        self.files
+            .iter()
+            .map(|(path, (modified, _))| (path.clone(), *modified))
+            .collect()
+    }
+
+    fn set_error(&mut self, key: String, message: String) {
+        if self.errors.get(&key) != Some(&message) {
+            error!("Error loading map {key}: {message}");
+            self.errors.insert(key, message);
+        }
+    }
+
+    fn apply_scan(&mut self, dir: &Path, scan: Result<Vec<FileChange>, String>) {
+        let changes = match scan {
+            Ok(changes) => {
+                self.errors.remove(&dir.display().to_string());
+                changes
+            }
+            Err(e) => {
+                self.set_error(dir.display().to_string(), e);
+                return;
+            }
+        };
+        for change in changes {
+            match change {
+                FileChange::Removed(path) => {
+                    if let Some((_, Some(name))) = self.files.remove(&path) {
+                        info!("Map file {} was removed, unloading {name}", path.display());
+                        self.maps.remove(&name);
+                    }
+                    self.courses.remove(&path);
+                    self.errors.remove(&path.display().to_string());
+                }
+                FileChange::Modified(path, modified, result) => {
+                    let previous_name = self.files.get(&path).and_then(|(_, name)| name.clone());
+                    let result = result.and_then(|file| match file {
+                        MapFile::Map(map) => self
+                            .check_name_free(*map, previous_name.as_ref())
+                            .map(|map| MapFile::Map(Box::new(map))),
+                        course @ MapFile::Course(_) => Ok(course),
+                    });
+                    match result {
+                        Ok(MapFile::Map(map)) => {
+                            self.courses.remove(&path);
+                            if let Some(name) = &previous_name {
+                                self.maps.remove(name);
+                                info!("Reloaded map {} from {}", map.name, path.display());
+                            }
+                            self.errors.remove(&path.display().to_string());
+                            self.insert(*map, Some((path, modified)));
+                        }
+                        Ok(MapFile::Course(course)) => {
+                            // the map itself is replaced once the course is composed
+                            self.files.insert(path.clone(), (modified, previous_name));
+                            self.courses.insert(path, course);
+                        }
+                        Err(e) => {
+                            self.set_error(path.display().to_string(), e);
+                            // remember the modification time so that the file isn't re-parsed until it changes again
+                            self.files.insert(path, (modified, previous_name));
+                        }
+                    }
+                }
+            }
+        }
+        self.compose_courses();
+    }
+
+    fn check_name_free(
+        &self,
+        map: GameMap,
+        previous_name: Option<&String>,
+    ) -> Result<GameMap, String> {
+        if previous_name != Some(&map.name) && self.contains(&map.name) {
+            Err(format!("another map is already named {}", map.name))
+        } else {
+            Ok(map)
+        }
+    }
+
+    /// Rebuilds the maps of all courses from the current boards
+    ///
+    /// Courses can only use boards that are plain maps, not other courses. If composing fails, the
+    /// previous version of the course stays loaded, same as for a map file that fails to parse.
+    fn compose_courses(&mut self) {
+        let course_map_names: Vec<&String> = self
+            .courses
+            .keys()
+            .filter_map(|path| self.files.get(path)?.1.as_ref())
+            .collect();
+        let composed: Vec<(PathBuf, Result<GameMap, String>)> = self
+            .courses
+            .iter()
+            .map(|(path, course)| {
+                let result = course.compose(|name| {
+                    self.maps
+                        .get(name)
+                        .filter(|_| !course_map_names.iter().any(|n| *n == name))
+                });
+                (path.clone(), result)
+            })
+            .collect();
+        for (path, result) in composed {
+            let (modified, previous_name) = self.files[&path].clone();
+            match result.and_then(|map| self.check_name_free(map, previous_name.as_ref())) {
+                Ok(map) => {
+                    if let Some(name) = &previous_name {
+                        self.maps.remove(name);
+                    }
+                    self.errors.remove(&path.display().to_string());
+                    self.insert(map, Some((path, modified)));
+                }
+                Err(e) => self.set_error(path.display().to_string(), e),
+            }
+        }
+    }
+}
+
+/// Reads and parses all files in the directory that were added or modified since the given versions
+///
+/// Done without holding the registry lock, so that listing maps isn't blocked by parsing
+fn scan_dir(
+    dir: &Path,
+    known_files: &HashMap<PathBuf, SystemTime>,
+) -> Result<Vec<FileChange>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| e.to_string())?;
+    let mut changes = Vec::new();
+    let mut present_files = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+            continue;
+        };
+        present_files.push(path.clone());
+        if known_files.get(&path) == Some(&modified) {
+            continue;
+        }
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                if text.starts_with("Course ") {
+                    Course::parse(&text, "").map(MapFile::Course)
+                } else {
+                    GameMap::parse(&text, "").map(|map| MapFile::Map(Box::new(map)))
+                }
+                .map_err(|e| e.to_string())
+            });
+        changes.push(FileChange::Modified(path, modified, result));
+    }
+    changes.extend(
+        known_files
+            .keys()
+            .filter(|path| !present_files.contains(path))
+            .map(|path| FileChange::Removed(path.clone())),
+    );
+    Ok(changes)
+}
+
+/// Periodically checks the maps directory and reloads any added, modified or removed maps
+pub async fn watch_dir(maps: Maps, dir: PathBuf) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (known_files, had_dir_error) = {
+            let registry = maps.read().await;
+            (
+                registry.file_versions(),
+                registry.errors.contains_key(&dir.display().to_string()),
+            )
+        };
+        let scan = scan_dir(&dir, &known_files);
+        if !had_dir_error && scan.as_ref().is_ok_and(Vec::is_empty) {
+            continue;
+        }
+        maps.write().await.apply_scan(&dir, scan);
+    }
+}
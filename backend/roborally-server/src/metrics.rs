@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use roborally_structs::transport::ServerMessage;
+
+use crate::game::Game;
+
+/// Counters shown on `GET /metrics`, see [`render`]
+pub static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the round duration buckets, in seconds
+const ROUND_DURATION_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// Card names come from clients, so past this many different cards, errors of new ones are counted together
+const MAX_CARD_LABELS: usize = 1000;
+const OTHER_CARDS_LABEL: &str = "(other)";
+
+pub struct Metrics {
+    rounds: AtomicU64,
+    /// Counts of rounds that took at most the corresponding [`ROUND_DURATION_BUCKETS`] time
+    round_duration_buckets: [AtomicU64; ROUND_DURATION_BUCKETS.len()],
+    round_duration_micros: AtomicU64,
+    script_errors: Mutex<BTreeMap<String, u64>>,
+    send_failures: AtomicU64,
+    bytes_sent: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            rounds: AtomicU64::new(0),
+            round_duration_buckets: [const { AtomicU64::new(0) }; ROUND_DURATION_BUCKETS.len()],
+            round_duration_micros: AtomicU64::new(0),
+            script_errors: Mutex::new(BTreeMap::new()),
+            send_failures: AtomicU64::new(0),
+            bytes_sent: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts a finished round, the duration shouldn't include the time the game was paused
+    pub fn round_processed(&self, duration: Duration) {
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+        let secs = duration.as_secs_f64();
+        for (bound, count) in ROUND_DURATION_BUCKETS
+            .iter()
+            .zip(&self.round_duration_buckets)
+        {
+            if secs <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.round_duration_micros.fetch_add(
+            duration.as_micros().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn script_error(&self, card: &str) {
+        let mut errors = self.script_errors.lock().unwrap();
+        if errors.len() >= MAX_CARD_LABELS && !errors.contains_key(card) {
+            *errors.entry(OTHER_CARDS_LABEL.to_owned()).or_default() += 1;
+        } else {
+            *errors.entry(card.to_owned()).or_default() += 1;
+        }
+    }
+
+    pub fn send_failed(&self) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_sent(&self, msg: &ServerMessage, bytes: usize) {
+        *self
+            .bytes_sent
+            .lock()
+            .unwrap()
+            .entry(message_kind(msg))
+            .or_default() += bytes as u64;
+    }
+}
+
+const fn message_kind(msg: &ServerMessage) -> &'static str {
+    match msg {
+        ServerMessage::Notice(_) => "Notice",
+        ServerMessage::GameLog(_) => "GameLog",
+        ServerMessage::GeneralState(_) => "GeneralState",
+        ServerMessage::ProgrammingState(_) => "ProgrammingState",
+        ServerMessage::AnimatedState(_) => "AnimatedState",
+        ServerMessage::AnimatedStateDelta(_) => "AnimatedStateDelta",
+        ServerMessage::Hello(_) => "Hello",
+        ServerMessage::Error(_) => "Error",
+        ServerMessage::Lobby(_) => "Lobby",
+        ServerMessage::Chat(_) => "Chat",
+    }
+}
+
+/// Escapes a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}").unwrap();
+}
+
+/// Writes all metrics in the Prometheus text exposition format
+///
+/// `games` are the current games, which the gauges are computed from
+pub fn render<'a>(games: impl Iterator<Item = &'a Game>) -> String {
+    let (mut game_count, mut player_count) = (0, 0);
+    for game in games {
+        game_count += 1;
+        player_count += (game.player_connections.iter())
+            .filter(|c| c.read().unwrap().upgrade().is_some())
+            .count();
+    }
+    let m = &METRICS;
+    let mut out = String::new();
+
+    write_header(
+        &mut out,
+        "roborally_games_active",
+        "gauge",
+        "Games that weren't removed yet",
+    );
+    writeln!(out, "roborally_games_active {game_count}").unwrap();
+
+    write_header(
+        &mut out,
+        "roborally_players_connected",
+        "gauge",
+        "Players connected to a seat, there are no spectators",
+    );
+    writeln!(out, "roborally_players_connected {player_count}").unwrap();
+
+    let rounds = m.rounds.load(Ordering::Relaxed);
+    write_header(
+        &mut out,
+        "roborally_rounds_processed_total",
+        "counter",
+        "Rounds that were run",
+    );
+    writeln!(out, "roborally_rounds_processed_total {rounds}").unwrap();
+
+    write_header(
+        &mut out,
+        "roborally_round_duration_seconds",
+        "histogram",
+        "Time from all programs being submitted to the end of the round",
+    );
+    for (bound, count) in ROUND_DURATION_BUCKETS.iter().zip(&m.round_duration_buckets) {
+        let count = count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "roborally_round_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "roborally_round_duration_seconds_bucket{{le=\"+Inf\"}} {rounds}"
+    )
+    .unwrap();
+    let duration_sum = Duration::from_micros(m.round_duration_micros.load(Ordering::Relaxed));
+    writeln!(
+        out,
+        "roborally_round_duration_seconds_sum {}",
+        duration_sum.as_secs_f64()
+    )
+    .unwrap();
+    writeln!(out, "roborally_round_duration_seconds_count {rounds}").unwrap();
+
+    write_header(
+        &mut out,
+        "roborally_script_errors_total",
+        "counter",
+        "Errors of card scripts, by card",
+    );
+    for (card, count) in m.script_errors.lock().unwrap().iter() {
+        let card = escape_label(card);
+        writeln!(
+            out,
+            "roborally_script_errors_total{{card=\"{card}\"}} {count}"
+        )
+        .unwrap();
+    }
+
+    write_header(
+        &mut out,
+        "roborally_websocket_send_failures_total",
+        "counter",
+        "Websocket messages that couldn't be sent",
+    );
+    let send_failures = m.send_failures.load(Ordering::Relaxed);
+    writeln!(
+        out,
+        "roborally_websocket_send_failures_total {send_failures}"
+    )
+    .unwrap();
+
+    write_header(
+        &mut out,
+        "roborally_websocket_sent_bytes_total",
+        "counter",
+        "Size of encoded messages sent to clients, by message type",
+    );
+    for (kind, bytes) in m.bytes_sent.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "roborally_websocket_sent_bytes_total{{message=\"{kind}\"}} {bytes}"
+        )
+        .unwrap();
+    }
+    out
+}
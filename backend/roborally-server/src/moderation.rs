@@ -0,0 +1,282 @@
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use roborally_structs::{
+    logging::info,
+    transport::{ErrorCode, GameError},
+};
+
+use crate::{game::Game, game_connection::SocketMessage, lobby::require_host, Games};
+
+/// Longest time a round stays paused, then the game is resumed
+const MAX_PAUSE: Duration = Duration::from_secs(30 * 60);
+
+/// How long a kicked player can't join the game again
+const KICK_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// Holds the round between registers while the game is paused
+#[derive(Default)]
+pub struct PauseGate {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    /// Time the round spent paused since the last [`PauseGate::take_waited`]
+    waited: Mutex<Duration>,
+}
+
+impl PauseGate {
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    fn set(&self, paused: bool) {
+        *self.paused.lock().unwrap() = paused;
+        self.resumed.notify_all();
+    }
+
+    /// Returns the time spent paused since the last call, and starts counting from zero
+    pub fn take_waited(&self) -> Duration {
+        std::mem::take(&mut *self.waited.lock().unwrap())
+    }
+
+    /// Blocks the thread until the game is resumed or aborted, returns `false` if it's still paused after [`MAX_PAUSE`]
+    fn wait(&self, aborted: &AtomicBool) -> bool {
+        let started = Instant::now();
+        let (guard, result) = (self.resumed)
+            .wait_timeout_while(self.paused.lock().unwrap(), MAX_PAUSE, |paused| {
+                *paused && !aborted.load(Ordering::Relaxed)
+            })
+            .unwrap();
+        drop(guard);
+        *self.waited.lock().unwrap() += started.elapsed();
+        !result.timed_out()
+    }
+}
+
+impl Game {
+    pub fn check_host(&self, seat: usize) -> Result<(), GameError> {
+        require_host(&self.state.read().unwrap(), seat)
+    }
+
+    /// Disconnects the player in given seat, the seat is free right away even if their client keeps the connection open
+    ///
+    /// The player can't join again for [`KICK_COOLDOWN`], see [`Game::check_not_kicked`].
+    /// `by` is shown to the player, e.g. "the host"
+    pub fn kick(&self, target: usize, by: &str) -> Result<(), GameError> {
+        let Some(conn) =
+            (self.player_connections.get(target)).and_then(|c| c.read().unwrap().upgrade())
+        else {
+            return Err(GameError::new(
+                ErrorCode::SeatNotFound,
+                "Nobody is sitting there",
+            ));
+        };
+        info!(game = self.name.as_str(), seat = target; "{} was kicked by {by}", conn.player_name);
+        conn.kicked.store(true, Ordering::Relaxed);
+        (self.kicked_players.lock().unwrap()).insert(conn.player_name.clone(), Instant::now());
+        *self.player_connections[target].write().unwrap() = Weak::new();
+        self.seat_freed(target);
+        self.send_general_state();
+        conn.sender
+            .send(SocketMessage::CloseWithError(GameError::new(
+                ErrorCode::Kicked,
+                format!("You were removed from the game by {by}"),
+            )))
+            .unwrap();
+        Ok(())
+    }
+
+    /// Refuses players that were kicked less than [`KICK_COOLDOWN`] ago
+    ///
+    /// Players are only told apart by their names, so this just stops the kicked client from
+    /// reconnecting right away
+    pub fn check_not_kicked(&self, player_name: &str) -> Result<(), GameError> {
+        let mut kicked_players = self.kicked_players.lock().unwrap();
+        kicked_players.retain(|_, kicked_at| kicked_at.elapsed() < KICK_COOLDOWN);
+        if kicked_players.contains_key(player_name) {
+            return Err(GameError::new(
+                ErrorCode::Kicked,
+                format!(
+                    "You were removed from this game, you can join again in {} minutes",
+                    KICK_COOLDOWN.as_secs() / 60
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pausing takes effect before the next register, including the first register of the next round
+    pub fn set_paused(&self, paused: bool) {
+        self.pause.set(paused);
+        self.send_general_state();
+    }
+
+    /// Stops the round after the current register and disconnects everyone
+    ///
+    /// This doesn't remove the game from the list of games, see [`Game::abort_and_remove`]
+    pub fn abort(&self, by: &str) {
+        self.aborted.store(true, Ordering::Relaxed);
+        self.pause.set(false);
+        for conn_lock in &self.player_connections {
+            let Some(conn) = conn_lock.read().unwrap().upgrade() else {
+                continue;
+            };
+            conn.sender
+                .send(SocketMessage::CloseWithError(GameError::new(
+                    ErrorCode::GameAborted,
+                    format!("The game was ended by {by}"),
+                )))
+                .unwrap();
+        }
+    }
+
+    /// Aborts the game and removes it from the list of games, so that nobody can join it anymore
+    pub async fn abort_and_remove(&self, games: &Games, by: &str) {
+        {
+            let mut games = games.write().await;
+            // the name might be taken by another game, if this one was already removed
+            if (games.get(&self.name)).is_some_and(|g| ptr::eq(g.as_ref(), self)) {
+                games.remove(&self.name);
+            }
+        }
+        self.abort(by);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    /// Called by the round between registers, returns whether the round should continue
+    pub fn wait_while_paused(&self) -> bool {
+        if self.pause.is_paused() {
+            let round = self.state.read().unwrap().round;
+            info!(game = self.name.as_str(), round; "Round paused");
+            if !self.pause.wait(&self.aborted) {
+                info!(game = self.name.as_str(), round; "Pause timed out");
+                self.set_paused(false);
+            }
+            if self.is_aborted() {
+                return false;
+            }
+            info!(game = self.name.as_str(), round; "Round resumed");
+        }
+        !self.is_aborted()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use roborally_structs::{
+        game_map::GameMap,
+        position::{Direction, Position},
+        tile::{Grid, Tile},
+        transport::PROTOCOL_VERSION,
+    };
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use super::*;
+    use crate::{chat::RateLimiter, game::NewGameData, game_connection::PlayerConnection};
+
+    pub fn game() -> Arc<Game> {
+        let size = Position { x: 2, y: 1 };
+        let map = GameMap {
+            name: "test".to_owned(),
+            tiles: Grid::new(vec![Tile::default(); 2], size).unwrap(),
+            antenna: Position { x: 0, y: 0 },
+            reboot_token: (Position { x: 1, y: 0 }, Direction::Up),
+            checkpoints: vec![Position { x: 1, y: 0 }],
+            spawn_points: vec![
+                (Position { x: 0, y: 0 }, Direction::Up),
+                (Position { x: 1, y: 0 }, Direction::Up),
+            ],
+            lasers: Vec::new(),
+            custom_tiles: Vec::new(),
+            script: None,
+        };
+        Game::new(map, NewGameData::sandbox("test".to_owned(), 2, Vec::new())).unwrap()
+    }
+
+    fn connect(
+        game: &Arc<Game>,
+        seat: usize,
+    ) -> (Arc<PlayerConnection>, UnboundedReceiver<SocketMessage>) {
+        connect_client(game, seat, &format!("player {seat}"), PROTOCOL_VERSION)
+    }
+
+    /// Seats a connection, its messages end up in the returned receiver
+    pub fn connect_client(
+        game: &Arc<Game>,
+        seat: usize,
+        player_name: &str,
+        protocol_version: u32,
+    ) -> (Arc<PlayerConnection>, UnboundedReceiver<SocketMessage>) {
+        let (sender, receiver) = unbounded_channel();
+        let conn = Arc::new(PlayerConnection {
+            player_name: player_name.to_owned(),
+            game: Arc::clone(game),
+            seat: Arc::new(AtomicUsize::new(seat)),
+            sender,
+            last_animated_state: Mutex::new(None),
+            protocol_version,
+            capabilities: Vec::new(),
+            kicked: AtomicBool::new(false),
+            chat_rate_limit: RateLimiter::default(),
+            games: Games::default(),
+        });
+        *game.player_connections[seat].write().unwrap() = Arc::downgrade(&conn);
+        game.player_joined(seat);
+        (conn, receiver)
+    }
+
+    #[test]
+    fn kicked_player_cant_rejoin_but_others_can_take_the_seat() {
+        let game = game();
+        let (host, _host_rx) = connect(&game, 0);
+        let (kicked, _kicked_rx) = connect(&game, 1);
+        game.set_ready(1, true).unwrap();
+
+        game.kick(1, "the host").unwrap();
+        // the kicked connection is still alive, because its client didn't close it yet
+        assert!(kicked.kicked.load(Ordering::Relaxed));
+        assert!(game.player_connections[1]
+            .read()
+            .unwrap()
+            .upgrade()
+            .is_none());
+        assert!(!game.state.read().unwrap().lobby.as_ref().unwrap().ready[1]);
+
+        let err = game.check_not_kicked(&kicked.player_name).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Kicked);
+        game.check_not_kicked("someone else").unwrap();
+        let (rejoined, _rejoined_rx) = connect_client(&game, 1, "someone else", PROTOCOL_VERSION);
+        game.set_ready(1, true).unwrap();
+        game.player_left(&kicked);
+        let seat_conn = game.player_connections[1]
+            .read()
+            .unwrap()
+            .upgrade()
+            .unwrap();
+        assert!(Arc::ptr_eq(&seat_conn, &rejoined));
+        assert!(game.state.read().unwrap().lobby.as_ref().unwrap().ready[1]);
+        assert_eq!(game.state.read().unwrap().host, Some(host.seat()));
+    }
+
+    #[tokio::test]
+    async fn aborted_game_is_removed_right_away() {
+        let game = game();
+        let games = Games::default();
+        (games.write().await).insert(game.name.clone(), Arc::clone(&game));
+        let (_host, _host_rx) = connect(&game, 0);
+
+        game.abort_and_remove(&games, "the host").await;
+        assert!(game.is_aborted());
+        assert!(games.read().await.is_empty());
+    }
+}
@@ -9,6 +9,9 @@ use roborally_structs::{
     tile::{DirectionBools, Grid, Tile},
     tile_type::TileType,
 };
+use serde::Serialize;
+
+use crate::course::{Course, CourseBoard};
 
 fn checked_split_in_two<'a, T: std::str::pattern::Pattern<'a>>(
     s: &'a str,
@@ -23,15 +26,28 @@ fn checked_split_in_two<'a, T: std::str::pattern::Pattern<'a>>(
 }
 
 fn format_parse_error(name: &str, message: &str, value: &str) -> ParseError {
-    ParseError(format!("Error parsing {name}: {message}: `{value}`"))
+    ParseError {
+        name: name.to_owned(),
+        message: message.to_owned(),
+        value: value.to_owned(),
+    }
 }
 
-#[derive(Debug)]
-pub struct ParseError(String);
+#[derive(Debug, Serialize)]
+pub struct ParseError {
+    /// Path to the invalid part of input, such as `map.lines[3][5].walls`
+    pub name: String,
+    pub message: String,
+    pub value: String,
+}
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(
+            f,
+            "Error parsing {}: {}: `{}`",
+            self.name, self.message, self.value
+        )
     }
 }
 
@@ -259,6 +275,48 @@ impl Parse for String {
     }
 }
 
+/// Splits a line of space-separated `key=value` props
+fn parse_props<'a>(line: &'a str, name: &str) -> Result<HashMap<&'a str, &'a str>, ParseError> {
+    let mut props = HashMap::new();
+    for propdef in line.split(' ') {
+        let (key, prop_value) = checked_split_in_two(propdef, '=').ok_or_else(|| {
+            format_parse_error(
+                name,
+                "prop definition doesn't follow syntax `key=value`",
+                propdef,
+            )
+        })?;
+        props.insert(key, prop_value);
+    }
+    Ok(props)
+}
+
+fn check_no_extra_props(props: HashMap<&str, &str>, name: &str) -> Result<(), ParseError> {
+    if props.is_empty() {
+        Ok(())
+    } else {
+        Err(format_parse_error(
+            name,
+            "extra props",
+            &props
+                .into_iter()
+                .map(|(k, v)| format!("{k}: `{v}`"))
+                .intersperse(", ".to_owned())
+                .collect::<String>(),
+        ))
+    }
+}
+
+/// Map and course names are shown in the UI and used in URLs
+fn has_valid_name_length(name: &str) -> bool {
+    (3..=20).contains(&name.len())
+}
+
+fn has_valid_name_chars(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
 #[allow(clippy::type_complexity)]
 /// Utility function to reduce repetition when extracting props from map header
 fn get_parsed_prop<T: Parse>(
@@ -288,7 +346,11 @@ fn get_parsed_prop<T: Parse>(
 /// dir    : u | r | d | l
 /// ```
 ///
-/// Then follow Size.y remaining lines
+/// Then follow Size.y tile lines. Besides the tile types described at [`TileType`], these can
+/// contain custom tiles `C{name}`, where the name consists of `[a-z0-9_]`.
+///
+/// Optionally, the tile lines are followed by a line `Script:`, and all remaining lines are a
+/// Rhai script with hooks for the custom tiles and other events.
 impl Parse for GameMap {
     #[allow(clippy::too_many_lines)]
     fn parse(value: &str, name: &str) -> Result<Self, ParseError> {
@@ -302,21 +364,12 @@ impl Parse for GameMap {
         let spawn_points: Vec<(Position, Direction)>;
         let lasers: Vec<(Position, Direction)>;
 
-        let mut props = HashMap::new();
-        for propdef in lines
-            .next()
-            .ok_or_else(|| format_parse_error(name, "no lines in input", value))?
-            .split(' ')
-        {
-            let (key, prop_value) = checked_split_in_two(propdef, '=').ok_or_else(|| {
-                format_parse_error(
-                    name,
-                    "prop definition doesn't follow syntax `key=value`",
-                    propdef,
-                )
-            })?;
-            props.insert(key, prop_value);
-        }
+        let mut props = parse_props(
+            lines
+                .next()
+                .ok_or_else(|| format_parse_error(name, "no lines in input", value))?,
+            name,
+        )?;
 
         let size: Position = get_parsed_prop(
             &mut props,
@@ -328,11 +381,24 @@ impl Parse for GameMap {
             )],
         )?;
 
+        let mut custom_tiles = Vec::new();
         let tile_lines: Vec<Vec<Tile>> = lines
+            .by_ref()
+            .take_while(|line| *line != "Script:")
             .enumerate()
             .map(|(i, line)| {
                 let line_name = &format!("{name}.lines[{i}]");
-                let line_tiles = <Vec<Tile>>::parse(line, line_name)?;
+                let line_tiles = line
+                    .split(';')
+                    .enumerate()
+                    .map(|(j, tile)| {
+                        parse_tile_with_custom(
+                            tile,
+                            &format!("{line_name}[{j}]"),
+                            &mut custom_tiles,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
                 if line_tiles.len() == size.x as usize {
                     Ok(line_tiles)
                 } else {
@@ -355,6 +421,18 @@ impl Parse for GameMap {
         let tiles = Grid::new(tile_lines.into_iter().flatten().collect(), size)
             .map_err(|e| format_parse_error(name, &e, &format!("{size:?}")))?;
 
+        let script_lines: Vec<&str> = lines.collect();
+        let script = if script_lines.is_empty() {
+            None
+        } else {
+            let script = script_lines.join("\n");
+            // only checks the syntax, functions are resolved when called
+            rhai::Engine::new()
+                .compile(&script)
+                .map_err(|e| format_parse_error(&format!("{name}.script"), &e.to_string(), ""))?;
+            Some(script)
+        };
+
         {
             let mut is_in_bounds = |p: &Position| size.contains(*p);
             let mut faces_into_map = |(pos, dir): &(Position, Direction)| {
@@ -375,14 +453,11 @@ impl Parse for GameMap {
                 "Name",
                 &mut [
                     (
-                        &mut |s: &String| s.len() <= 20 && s.len() >= 3,
+                        &mut |s: &String| has_valid_name_length(s),
                         "map name must be 3-20 characters long",
                     ),
                     (
-                        &mut |s: &String| {
-                            s.chars()
-                                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
-                        },
+                        &mut |s: &String| has_valid_name_chars(s),
                         "map name can only contain [a-zA-Z0-9_-]",
                     ),
                 ],
@@ -529,17 +604,7 @@ impl Parse for GameMap {
                 ],
             )?;
 
-            if !props.is_empty() {
-                return Err(format_parse_error(
-                    name,
-                    "extra props in header",
-                    &props
-                        .into_iter()
-                        .map(|(k, v)| format!("{k}: `{v}`"))
-                        .intersperse(", ".to_owned())
-                        .collect::<String>(),
-                ));
-            }
+            check_no_extra_props(props, name)?;
         }
 
         Ok(Self {
@@ -550,6 +615,303 @@ impl Parse for GameMap {
             checkpoints,
             spawn_points,
             lasers,
+            custom_tiles,
+            script,
+        })
+    }
+}
+
+/// Parses a tile, registering any new custom tile type to the list
+fn parse_tile_with_custom(
+    value: &str,
+    name: &str,
+    custom_tiles: &mut Vec<String>,
+) -> Result<Tile, ParseError> {
+    let Some(custom) = value.strip_prefix('C') else {
+        return Tile::parse(value, name);
+    };
+    let (custom_name, walls) = match checked_split_in_two(custom, ':') {
+        Some((custom_name, wallspec)) => (
+            custom_name,
+            DirectionBools::parse(wallspec, &format!("{name}.walls"))?,
+        ),
+        None => (custom, DirectionBools::default()),
+    };
+    if custom_name.is_empty()
+        || !custom_name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(format_parse_error(
+            name,
+            "custom tile name can only contain [a-z0-9_]",
+            value,
+        ));
+    }
+    let index = custom_tiles
+        .iter()
+        .position(|t| t == custom_name)
+        .unwrap_or_else(|| {
+            custom_tiles.push(custom_name.to_owned());
+            custom_tiles.len() - 1
+        });
+    let index = u8::try_from(index)
+        .map_err(|_| format_parse_error(name, "too many custom tile types", value))?;
+    Ok(Tile {
+        typ: TileType::Custom(index),
+        walls,
+    })
+}
+
+/// Same as [`get_parsed_prop`], but the prop may be missing
+fn get_optional_parsed_prop<T: Parse>(
+    props: &mut HashMap<&str, &str>,
+    basename: &str,
+    propname: &str,
+) -> Result<Option<T>, ParseError> {
+    if props.contains_key(propname) {
+        get_parsed_prop(props, basename, propname, &mut []).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// First line is a header, the same as for a map, but prefixed with `Course` and only with
+/// some of the props (Antenna, Reboot and Checkpoints are optional):
+/// ```raw
+/// header : Course {prop}( {prop})*
+/// prop   : Name={name} | Size={pos} | Antenna={pos} | Reboot={pos}:{dir} | Checkpoints=[{pos}];+
+/// ```
+///
+/// Each following line places one board, which is referenced by its map name:
+/// ```raw
+/// board  : Board={name} Offset={pos}( Mirror=(h|v))?( Rotation=(0|90|180|270))?
+/// ```
+/// The board is first mirrored (horizontally or vertically), then rotated clockwise.
+impl Parse for Course {
+    fn parse(value: &str, name: &str) -> Result<Self, ParseError> {
+        let mut lines = value.lines();
+        let header = lines
+            .next()
+            .and_then(|line| line.strip_prefix("Course "))
+            .ok_or_else(|| {
+                format_parse_error(name, "expected header starting with `Course `", value)
+            })?;
+        let mut props = parse_props(header, name)?;
+        let course_name = get_parsed_prop(
+            &mut props,
+            name,
+            "Name",
+            &mut [
+                (
+                    &mut |s: &String| has_valid_name_length(s),
+                    "course name must be 3-20 characters long",
+                ),
+                (
+                    &mut |s: &String| has_valid_name_chars(s),
+                    "course name can only contain [a-zA-Z0-9_-]",
+                ),
+            ],
+        )?;
+        let size: Position = get_parsed_prop(
+            &mut props,
+            name,
+            "Size",
+            &mut [
+                (
+                    &mut |s: &Position| s.x > 0 && s.y > 0,
+                    "course dimensions must be non-zero",
+                ),
+                (
+                    &mut |s: &Position| s.x <= 100 && s.y <= 100,
+                    "course can be at most 100x100",
+                ),
+            ],
+        )?;
+        let antenna = get_optional_parsed_prop(&mut props, name, "Antenna")?;
+        let reboot_token = get_optional_parsed_prop(&mut props, name, "Reboot")?;
+        let checkpoints = get_optional_parsed_prop(&mut props, name, "Checkpoints")?;
+        check_no_extra_props(props, name)?;
+
+        let boards = lines
+            .enumerate()
+            .map(|(i, line)| {
+                let line_name = &format!("{name}.boards[{i}]");
+                let mut board_props = parse_props(line, line_name)?;
+                let board = CourseBoard {
+                    map_name: get_parsed_prop(&mut board_props, line_name, "Board", &mut [])?,
+                    offset: get_parsed_prop(
+                        &mut board_props,
+                        line_name,
+                        "Offset",
+                        &mut [(
+                            &mut |p: &Position| p.x >= 0 && p.y >= 0,
+                            "must not be negative",
+                        )],
+                    )?,
+                    rotation: get_optional_parsed_prop::<usize>(
+                        &mut board_props,
+                        line_name,
+                        "Rotation",
+                    )?
+                    .map_or(Ok(0), |degrees| {
+                        if degrees % 90 == 0 && degrees < 360 {
+                            Ok((degrees / 90) as u8)
+                        } else {
+                            Err(format_parse_error(
+                                line_name,
+                                "rotation must be one of 0, 90, 180, 270",
+                                &degrees.to_string(),
+                            ))
+                        }
+                    })?,
+                    mirror: get_optional_parsed_prop::<String>(
+                        &mut board_props,
+                        line_name,
+                        "Mirror",
+                    )?
+                    .map(|axis| match axis.as_str() {
+                        "h" => Ok(true),
+                        "v" => Ok(false),
+                        _ => Err(format_parse_error(
+                            line_name,
+                            "mirror must be either h or v",
+                            &axis,
+                        )),
+                    })
+                    .transpose()?,
+                };
+                check_no_extra_props(board_props, line_name)?;
+                Ok(board)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            name: course_name,
+            size,
+            antenna,
+            reboot_token,
+            checkpoints,
+            boards,
         })
     }
 }
+
+/// Inverse of [`Parse`] - produces the textual representation that parses back into the same value
+pub trait Unparse {
+    fn unparse(&self) -> String;
+}
+
+impl Unparse for Position {
+    fn unparse(&self) -> String {
+        format!("{},{}", self.x, self.y)
+    }
+}
+
+impl Unparse for Direction {
+    fn unparse(&self) -> String {
+        use Direction::*;
+        match self {
+            Up => "u",
+            Right => "r",
+            Down => "d",
+            Left => "l",
+        }
+        .to_owned()
+    }
+}
+
+impl Unparse for (Position, Direction) {
+    fn unparse(&self) -> String {
+        format!("{}:{}", self.0.unparse(), self.1.unparse())
+    }
+}
+
+impl<T: Unparse> Unparse for Vec<T> {
+    fn unparse(&self) -> String {
+        self.iter()
+            .map(Unparse::unparse)
+            .intersperse(";".to_owned())
+            .collect()
+    }
+}
+
+impl Unparse for TileType {
+    fn unparse(&self) -> String {
+        use TileType::*;
+        match self {
+            Void => "V".to_owned(),
+            Floor => "F".to_owned(),
+            Belt(is_fast, dir) => format!("B{}{}", if *is_fast { 'f' } else { 's' }, dir.unparse()),
+            PushPanel(dir, divisor, remainder) => {
+                format!("P{}{divisor}+{remainder}", dir.unparse())
+            }
+            Rotation(is_cw) => format!("R{}", if *is_cw { "cw" } else { "ccw" }),
+            // the name is only known to the map, see `impl Unparse for GameMap`
+            Custom(i) => format!("C{i}"),
+        }
+    }
+}
+
+impl Unparse for DirectionBools {
+    fn unparse(&self) -> String {
+        self.to_items()
+            .into_iter()
+            .filter(|(_, is_set)| *is_set)
+            .map(|(dir, _)| dir.unparse())
+            .collect()
+    }
+}
+
+impl Unparse for Tile {
+    fn unparse(&self) -> String {
+        let walls = self.walls.unparse();
+        if walls.is_empty() {
+            self.typ.unparse()
+        } else {
+            format!("{}:{walls}", self.typ.unparse())
+        }
+    }
+}
+
+impl Unparse for GameMap {
+    fn unparse(&self) -> String {
+        let size = self.tiles.size();
+        let mut result = format!(
+            "Name={} Size={} Antenna={} Reboot={} Checkpoints={} Spawnpoints={} Lasers={}\n",
+            self.name,
+            size.unparse(),
+            self.antenna.unparse(),
+            self.reboot_token.unparse(),
+            self.checkpoints.unparse(),
+            self.spawn_points.unparse(),
+            self.lasers.unparse(),
+        );
+        for row in self.tiles.vec().chunks(size.x as usize) {
+            let row = row
+                .iter()
+                .map(|tile| {
+                    let TileType::Custom(i) = tile.typ else {
+                        return tile.unparse();
+                    };
+                    let custom_name = &self.custom_tiles[i as usize];
+                    let walls = tile.walls.unparse();
+                    if walls.is_empty() {
+                        format!("C{custom_name}")
+                    } else {
+                        format!("C{custom_name}:{walls}")
+                    }
+                })
+                .intersperse(";".to_owned())
+                .collect::<String>();
+            result.push_str(&row);
+            result.push('\n');
+        }
+        if let Some(script) = &self.script {
+            result.push_str("Script:\n");
+            result.push_str(script);
+            result.push('\n');
+        }
+        result
+    }
+}
@@ -5,6 +5,7 @@ use roborally_structs::{
     card::Card,
     game_state::player_public_state::PlayerPublicState,
     position::{Direction, Position},
+    transport::{ErrorCode, GameError},
 };
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +18,14 @@ pub struct Player {
     pub hand: Vec<Card>,
     pub discard_pile: Vec<Card>,
     pub prepared_cards: Option<Vec<Card>>,
+    /// Cards drawn by card scripts during the round, they are added to the hand for the next programming phase
+    ///
+    /// This and the following fields are last and have defaults, so that older savefiles can still be loaded
+    #[serde(default)]
+    pub drawn_cards: Vec<Card>,
+    /// How many times the robot rebooted during the game
+    #[serde(default)]
+    pub reboot_count: usize,
 }
 
 impl Player {
@@ -43,32 +52,69 @@ impl Player {
                 .chain(repeat(Card::Again).take(again_count))
                 .collect(),
             prepared_cards: None,
+            drawn_cards: Vec::new(),
+            reboot_count: 0,
         };
         p.hand = p.draw_n_cards(draw_cards);
         p
     }
 
-    pub fn draw_one_card(&mut self) -> Card {
-        if let Some(c) = self.draw_pile.pop() {
-            c
-        } else {
+    /// All cards of the player, wherever they are
+    pub fn all_cards(&self) -> impl Iterator<Item = &Card> {
+        self.draw_pile
+            .iter()
+            .chain(&self.hand)
+            .chain(&self.discard_pile)
+            .chain(self.prepared_cards.iter().flatten())
+            .chain(&self.drawn_cards)
+    }
+
+    /// Returns `None` only if both the draw pile and the discard pile are empty, which can happen
+    /// when card scripts draw a lot of cards
+    pub fn draw_one_card(&mut self) -> Option<Card> {
+        if self.draw_pile.is_empty() {
             self.draw_pile = mem::take(&mut self.discard_pile);
             self.draw_pile.shuffle(&mut thread_rng());
-            self.draw_pile.pop().unwrap()
         }
+        self.draw_pile.pop()
     }
 
+    /// Draws up to n cards
     pub fn draw_n_cards(&mut self, n: usize) -> Vec<Card> {
-        (0..n).map(|_| self.draw_one_card()).collect()
+        (0..n).map_while(|_| self.draw_one_card()).collect()
+    }
+
+    /// The top n cards of the draw pile (fewer if there isn't that many), in the order they would be drawn
+    pub fn peek_draw_pile(&self, n: usize) -> impl Iterator<Item = &Card> {
+        self.draw_pile.iter().rev().take(n)
     }
 
     pub fn draw_spam(&mut self) {
         self.discard_pile.push(Card::SPAM);
     }
 
-    pub fn program(&mut self, cards: Vec<Card>) -> Result<(), String> {
+    /// Validates that a card script may change the given register: only registers that weren't revealed
+    /// yet can be changed
+    pub fn unrevealed_register(
+        &self,
+        current_register: usize,
+        register_i: i64,
+    ) -> Result<usize, String> {
+        let Some(cards) = &self.prepared_cards else {
+            return Err("Registers can only be changed while they are being executed".to_owned());
+        };
+        if register_i as usize <= current_register || register_i as usize >= cards.len() {
+            return Err("Only registers after the current one can be changed".to_owned());
+        }
+        Ok(register_i as usize)
+    }
+
+    pub fn program(&mut self, cards: Vec<Card>) -> Result<(), GameError> {
         if self.prepared_cards.is_some() {
-            return Err("Cards already set".to_owned());
+            return Err(GameError::new(
+                ErrorCode::CardsAlreadySet,
+                "Cards already set",
+            ));
         }
 
         let mut used_hand_indexes = vec![false; self.hand.len()];
@@ -80,8 +126,9 @@ impl Player {
                 }
             }
             // did not find this card (unused) in hand
-            return Err(format!(
-                "No cheating! {picked_card:?} isn't in your hand (enough times)"
+            return Err(GameError::new(
+                ErrorCode::InvalidProgram,
+                format!("No cheating! {picked_card:?} isn't in your hand (enough times)"),
             ));
         }
 
@@ -101,6 +101,432 @@ pub mod game_api {
             .is_some_and(|t| t.typ != roborally_structs::tile_type::TileType::Void)
     }
 
+    #[rhai_fn(pure)]
+    pub fn get_tile(game: &mut Game, pos: MapPosition) -> MapTile {
+        game.read()
+            .unwrap()
+            .game
+            .upgrade()
+            .unwrap()
+            .map
+            .tiles
+            .get(pos)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Name of the custom tile type at the position, unit for other tiles
+    #[rhai_fn(pure)]
+    pub fn get_custom_tile(game: &mut Game, pos: MapPosition) -> Dynamic {
+        let game_rules = game.read().unwrap().game.upgrade().unwrap();
+        match game_rules.map.tiles.get(pos).map(|t| t.typ) {
+            Some(roborally_structs::tile_type::TileType::Custom(i)) => {
+                Dynamic::from(game_rules.map.custom_tiles[i as usize].clone())
+            }
+            _ => Dynamic::UNIT,
+        }
+    }
+
+    /// Checks walls on both sides of the edge, the same way as when moving a player
+    #[rhai_fn(pure)]
+    pub fn is_wall_between(game: &mut Game, pos: MapPosition, direction: PlayerDirection) -> bool {
+        let dir: roborally_structs::position::Direction = direction.into();
+        let tiles = &game.read().unwrap().game.upgrade().unwrap().map.tiles;
+        tiles.get(pos).is_some_and(|t| t.walls.get(dir))
+            || tiles
+                .get(pos.moved_in_direction(dir))
+                .is_some_and(|t| t.walls.get(dir.rotated().rotated()))
+    }
+
+    #[rhai_fn(pure)]
+    pub fn get_checkpoints(game: &mut Game) -> rhai::Array {
+        game.read()
+            .unwrap()
+            .game
+            .upgrade()
+            .unwrap()
+            .map
+            .checkpoints
+            .iter()
+            .map(|pos| Dynamic::from(*pos))
+            .collect()
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_player_checkpoint(
+        game: &mut Game,
+        player_i: i64,
+    ) -> Result<i64, Box<EvalAltResult>> {
+        game.read()
+            .unwrap()
+            .players
+            .get(player_i as usize)
+            .map(|p| p.public_state.checkpoint as i64)
+            .ok_or_else(|| "There aren't that many players".into())
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_player_next_checkpoint(
+        game_lock: &mut Game,
+        player_i: i64,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        let Some(player) = game.players.get(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        Ok(game
+            .game
+            .upgrade()
+            .unwrap()
+            .map
+            .checkpoints
+            .get(player.public_state.checkpoint)
+            .map(|pos| Dynamic::from(*pos))
+            .unwrap_or_default())
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn is_player_rebooting(game: &mut Game, player_i: i64) -> Result<bool, Box<EvalAltResult>> {
+        game.read()
+            .unwrap()
+            .players
+            .get(player_i as usize)
+            .map(|p| p.public_state.is_rebooting)
+            .ok_or_else(|| "There aren't that many players".into())
+    }
+
+    #[rhai_fn(pure)]
+    pub fn get_antenna(game: &mut Game) -> MapPosition {
+        game.read().unwrap().game.upgrade().unwrap().map.antenna
+    }
+
+    #[rhai_fn(pure)]
+    pub fn get_reboot_token_position(game: &mut Game) -> MapPosition {
+        game.read()
+            .unwrap()
+            .game
+            .upgrade()
+            .unwrap()
+            .map
+            .reboot_token
+            .0
+    }
+
+    #[rhai_fn(pure)]
+    pub fn get_reboot_token_direction(game: &mut Game) -> PlayerDirection {
+        game.read()
+            .unwrap()
+            .game
+            .upgrade()
+            .unwrap()
+            .map
+            .reboot_token
+            .1
+            .to_continuous()
+    }
+
+    /// Names of cards in registers that were already revealed this round (up to and including the current one)
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_revealed_cards(
+        game_lock: &mut Game,
+        player_i: i64,
+    ) -> Result<rhai::Array, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        let Some(player) = game.players.get(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let Some(cards) = &player.prepared_cards else {
+            return Ok(rhai::Array::new());
+        };
+        let game_rules = game.game.upgrade().unwrap();
+        Ok(cards[..=game.running_state.0.min(cards.len() - 1)]
+            .iter()
+            .map(|card| Dynamic::from(game_rules.card_name(*card)))
+            .collect())
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_hand(
+        game_lock: &mut Game,
+        player_i: i64,
+    ) -> Result<rhai::Array, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        let Some(player) = game.players.get(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let game_rules = game.game.upgrade().unwrap();
+        Ok(player
+            .hand
+            .iter()
+            .map(|card| Dynamic::from(game_rules.card_name(*card)))
+            .collect())
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn peek_draw_pile(
+        game_lock: &mut Game,
+        player_i: i64,
+        count: i64,
+    ) -> Result<rhai::Array, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        let Some(player) = game.players.get(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let game_rules = game.game.upgrade().unwrap();
+        Ok(player
+            .peek_draw_pile(count.max(0) as usize)
+            .map(|card| Dynamic::from(game_rules.card_name(*card)))
+            .collect())
+    }
+
+    /// Returns the number of cards actually drawn, which is lower only if the player has no cards left
+    #[rhai_fn(pure, return_raw)]
+    pub fn draw_cards(
+        game_lock: &mut Game,
+        player_i: i64,
+        count: i64,
+    ) -> Result<i64, Box<EvalAltResult>> {
+        let mut game = game_lock.write().unwrap();
+        let Some(player) = game.players.get_mut(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let mut cards = player.draw_n_cards(count.max(0) as usize);
+        let drawn = cards.len() as i64;
+        player.drawn_cards.append(&mut cards);
+        Ok(drawn)
+    }
+
+    /// At most [`MAX_ARRAY_SIZE`](crate::script_limits::MAX_ARRAY_SIZE) cards can be given at once
+    #[rhai_fn(pure, return_raw)]
+    pub fn give_spam(
+        game_lock: &mut Game,
+        player_i: i64,
+        count: i64,
+    ) -> Result<(), Box<EvalAltResult>> {
+        use crate::script_limits::MAX_ARRAY_SIZE;
+        let Some(count) = usize::try_from(count)
+            .ok()
+            .filter(|count| *count <= MAX_ARRAY_SIZE)
+        else {
+            return Err(format!("Spam count must be between 0 and {MAX_ARRAY_SIZE}").into());
+        };
+        let mut game = game_lock.write().unwrap();
+        let Some(player) = game.players.get_mut(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        for _ in 0..count {
+            player.draw_spam();
+        }
+        Ok(())
+    }
+
+    /// Returns whether the card was found
+    #[rhai_fn(pure, return_raw)]
+    pub fn remove_from_discard_pile(
+        game_lock: &mut Game,
+        player_i: i64,
+        card_name: &str,
+    ) -> Result<bool, Box<EvalAltResult>> {
+        let mut game = game_lock.write().unwrap();
+        let game_rules = game.game.upgrade().unwrap();
+        let Some(player) = game.players.get_mut(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let Some(card_i) = player
+            .discard_pile
+            .iter()
+            .position(|card| game_rules.card_name(*card) == card_name)
+        else {
+            return Ok(false);
+        };
+        player.discard_pile.remove(card_i);
+        Ok(true)
+    }
+
+    /// Moves the card from a later register to the discard pile and replaces it with the top card of the draw pile
+    #[rhai_fn(pure, return_raw)]
+    pub fn discard_register(
+        game_lock: &mut Game,
+        player_i: i64,
+        register_i: i64,
+    ) -> Result<(), Box<EvalAltResult>> {
+        let mut game = game_lock.write().unwrap();
+        let current_register = game.running_state.0;
+        let Some(player) = game.players.get_mut(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let register = player.unrevealed_register(current_register, register_i)?;
+        let Some(replacement) = player.draw_one_card() else {
+            return Err("The player has no cards left to draw".into());
+        };
+        let discarded = std::mem::replace(
+            &mut player.prepared_cards.as_mut().unwrap()[register],
+            replacement,
+        );
+        player.discard_pile.push(discarded);
+        game.send_animation_item(&[], true);
+        Ok(())
+    }
+
+    /// Number of rounds finished so far
+    #[rhai_fn(pure)]
+    pub fn get_round(game_lock: &mut Game) -> i64 {
+        game_lock.read().unwrap().round as i64
+    }
+
+    /// How many times the robot rebooted during the game
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_player_reboot_count(
+        game_lock: &mut Game,
+        player_i: i64,
+    ) -> Result<i64, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        let Some(player) = game.players.get(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        Ok(player.reboot_count as i64)
+    }
+
+    /// Value stored for the player, or unit if the key isn't set
+    #[rhai_fn(pure, return_raw)]
+    pub fn get_player_value(
+        game_lock: &mut Game,
+        player_i: i64,
+        key: &str,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        let game = game_lock.read().unwrap();
+        if player_i as usize >= game.players.len() {
+            return Err("There aren't that many players".into());
+        }
+        Ok(game.store.get(Some(player_i as usize), key))
+    }
+
+    /// Storing unit removes the key
+    #[rhai_fn(pure, return_raw)]
+    pub fn set_player_value(
+        game_lock: &mut Game,
+        player_i: i64,
+        key: &str,
+        value: Dynamic,
+    ) -> Result<(), Box<EvalAltResult>> {
+        let mut game = game_lock.write().unwrap();
+        if player_i as usize >= game.players.len() {
+            return Err("There aren't that many players".into());
+        }
+        game.store
+            .set(Some(player_i as usize), key, value)
+            .map_err(Into::into)
+    }
+
+    #[rhai_fn(pure)]
+    pub fn get_game_value(game_lock: &mut Game, key: &str) -> Dynamic {
+        game_lock.read().unwrap().store.get(None, key)
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn set_game_value(
+        game_lock: &mut Game,
+        key: &str,
+        value: Dynamic,
+    ) -> Result<(), Box<EvalAltResult>> {
+        game_lock
+            .write()
+            .unwrap()
+            .store
+            .set(None, key, value)
+            .map_err(Into::into)
+    }
+
+    /// Moves the card from a later register to the discard pile and replaces it with a card from the player's hand
+    #[rhai_fn(pure, return_raw)]
+    pub fn replace_register_from_hand(
+        game_lock: &mut Game,
+        player_i: i64,
+        register_i: i64,
+        hand_i: i64,
+    ) -> Result<(), Box<EvalAltResult>> {
+        let mut game = game_lock.write().unwrap();
+        let current_register = game.running_state.0;
+        let Some(player) = game.players.get_mut(player_i as usize) else {
+            return Err("There aren't that many players".into());
+        };
+        let register = player.unrevealed_register(current_register, register_i)?;
+        if hand_i < 0 || hand_i as usize >= player.hand.len() {
+            return Err("There aren't that many cards in the player's hand".into());
+        }
+        let replacement = player.hand.remove(hand_i as usize);
+        let discarded = std::mem::replace(
+            &mut player.prepared_cards.as_mut().unwrap()[register],
+            replacement,
+        );
+        player.discard_pile.push(discarded);
+        game.send_animation_item(&[], true);
+        Ok(())
+    }
+
+    pub type MapTile = roborally_structs::tile::Tile;
+
+    /// One of `void`, `floor`, `belt`, `push_panel`, `rotation`, `custom`
+    #[rhai_fn(get = "kind", pure)]
+    pub fn tile_get_kind(tile: &mut MapTile) -> String {
+        use roborally_structs::tile_type::TileType::*;
+        match tile.typ {
+            Void => "void",
+            Floor => "floor",
+            Belt(..) => "belt",
+            PushPanel(..) => "push_panel",
+            Rotation(_) => "rotation",
+            Custom(_) => "custom",
+        }
+        .to_owned()
+    }
+
+    /// Direction of a belt or push panel, unit for other tiles
+    #[rhai_fn(get = "direction", pure)]
+    pub fn tile_get_direction(tile: &mut MapTile) -> Dynamic {
+        use roborally_structs::tile_type::TileType::*;
+        match tile.typ {
+            Belt(_, dir) | PushPanel(dir, ..) => Dynamic::from(dir.to_continuous()),
+            Void | Floor | Rotation(_) | Custom(_) => Dynamic::UNIT,
+        }
+    }
+
+    #[rhai_fn(get = "is_fast", pure)]
+    pub fn tile_get_is_fast(tile: &mut MapTile) -> bool {
+        matches!(
+            tile.typ,
+            roborally_structs::tile_type::TileType::Belt(true, _)
+        )
+    }
+
+    #[rhai_fn(get = "is_clockwise", pure)]
+    pub fn tile_get_is_clockwise(tile: &mut MapTile) -> bool {
+        matches!(
+            tile.typ,
+            roborally_structs::tile_type::TileType::Rotation(true)
+        )
+    }
+
+    #[rhai_fn(pure, return_raw)]
+    pub fn is_active_in_register(
+        tile: &mut MapTile,
+        register_i: i64,
+    ) -> Result<bool, Box<EvalAltResult>> {
+        let Ok(register_i) = usize::try_from(register_i) else {
+            return Err("There is no register with this index".into());
+        };
+        Ok(matches!(
+            tile.typ,
+            roborally_structs::tile_type::TileType::PushPanel(_, divisor, remainder)
+                if (register_i + 1) % divisor == remainder
+        ))
+    }
+
+    #[rhai_fn(pure)]
+    pub fn has_wall(tile: &mut MapTile, direction: PlayerDirection) -> bool {
+        tile.walls.get(direction.into())
+    }
+
     pub type MoveResult = crate::game_state::MoveResult;
     #[rhai_fn(get = "moved", pure)]
     pub fn move_result_get_moved(move_result: &mut MoveResult) -> bool {
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use roborally_structs::{
+    card::Card, game_map::GameMap, game_state::GameStatusInfo, tile_type::TileType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{CardInitializationDefinition, Game, NewGameData},
+    game_log::LogVisibility,
+    parser::{Parse, Unparse},
+    player::Player,
+    script_store::ScriptStore,
+};
+
+/// Everything needed to continue a game later, see [`Game::savefile`]
+///
+/// Variables that card scripts keep in their scope aren't saved, only the [`ScriptStore`]
+#[derive(Serialize, Deserialize)]
+pub struct Savefile {
+    pub map: GameMap,
+    pub again_count: usize,
+    pub card_definitions: Vec<CardInitializationDefinition>,
+    pub round_registers: usize,
+    pub draw_cards: usize,
+    pub log_visibility: LogVisibility,
+    pub mode_script: Option<String>,
+    /// Number of rounds played so far
+    pub round: usize,
+    pub players: Vec<Player>,
+    pub store: ScriptStore,
+    /// Last, so that older savefiles without it can still be loaded
+    #[serde(default)]
+    pub chat_events: bool,
+}
+
+/// Savefile from before mode scripts, see [`Savefile::decode`]
+#[derive(Deserialize)]
+struct SavefileWithoutMode {
+    map: GameMap,
+    again_count: usize,
+    card_definitions: Vec<CardInitializationDefinition>,
+    round_registers: usize,
+    draw_cards: usize,
+    log_visibility: LogVisibility,
+    players: Vec<Player>,
+    store: ScriptStore,
+}
+
+impl From<SavefileWithoutMode> for Savefile {
+    fn from(old: SavefileWithoutMode) -> Self {
+        Self {
+            map: old.map,
+            again_count: old.again_count,
+            card_definitions: old.card_definitions,
+            round_registers: old.round_registers,
+            draw_cards: old.draw_cards,
+            log_visibility: old.log_visibility,
+            mode_script: None,
+            round: 0,
+            players: old.players,
+            store: old.store,
+            chat_events: false,
+        }
+    }
+}
+
+impl Savefile {
+    /// Decodes a msgpack savefile, including ones saved before [`Savefile::mode_script`] and
+    /// [`Savefile::round`] were added, which count rounds from 0 again
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        rmp_serde::from_slice::<Self>(bytes).or_else(|e| {
+            rmp_serde::from_slice::<SavefileWithoutMode>(bytes)
+                .map(Self::from)
+                .map_err(|_| e.to_string())
+        })
+    }
+
+    /// The map checked the same way as a map file, by parsing it from its textual form
+    fn validated_map(&self) -> Result<GameMap, String> {
+        let custom_count = self.map.custom_tiles.len();
+        let has_unknown_tile = (self.map.tiles.vec().iter())
+            .any(|tile| matches!(tile.typ, TileType::Custom(i) if i as usize >= custom_count));
+        if has_unknown_tile {
+            return Err("Map has custom tiles that aren't defined".to_owned());
+        }
+        GameMap::parse(&self.map.unparse(), "savefile.map").map_err(|e| e.to_string())
+    }
+
+    /// Creates a new game in the saved state
+    pub fn into_game(self, name: String) -> Result<Arc<Game>, String> {
+        let player_count = self.players.len();
+        if self.store.player_count() != player_count {
+            return Err("Script store doesn't match the number of players".to_owned());
+        }
+        let card_count = self.card_definitions.len();
+        let has_unknown_card = self
+            .players
+            .iter()
+            .flat_map(Player::all_cards)
+            .any(|card| matches!(card, Card::Custom(card_i) if *card_i >= card_count));
+        if has_unknown_card {
+            return Err("Players have cards that aren't defined".to_owned());
+        }
+        let map = self.validated_map()?;
+        let size = map.tiles.size();
+        if !self
+            .players
+            .iter()
+            .all(|p| size.contains(p.public_state.position))
+        {
+            return Err("Players must be on the map".to_owned());
+        }
+        let has_wrong_program = self.players.iter().any(|p| {
+            p.prepared_cards
+                .as_ref()
+                .is_some_and(|cards| cards.len() != self.round_registers)
+        });
+        if has_wrong_program {
+            return Err("Programmed cards don't match the number of registers".to_owned());
+        }
+        let game = Game::new(
+            map,
+            NewGameData {
+                map_name: String::new(),
+                name,
+                random_map: None,
+                player_count,
+                card_set: None,
+                again_count: Some(self.again_count),
+                card_definitions: self.card_definitions,
+                log_visibility: self.log_visibility,
+                round_registers: self.round_registers,
+                draw_cards: self.draw_cards,
+                mode_script: self.mode_script,
+                chat_events: self.chat_events,
+            },
+        )?;
+        {
+            let mut state = game.state.write().unwrap();
+            state.hook_positions = self
+                .players
+                .iter()
+                .map(|p| p.public_state.position)
+                .collect();
+            state.players = self.players;
+            state.store = self.store;
+            state.round = self.round;
+            state.lobby = None;
+            state.status = GameStatusInfo::Programming;
+        }
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roborally_structs::{
+        game_state::player_public_state::PlayerPublicState,
+        position::{Direction, Position},
+        tile::{Grid, Tile},
+    };
+
+    use super::*;
+
+    /// [`Player`] as it was saved before [`Player::reboot_count`] was added
+    #[derive(Serialize)]
+    struct OldPlayer {
+        public_state: PlayerPublicState,
+        draw_pile: Vec<Card>,
+        hand: Vec<Card>,
+        discard_pile: Vec<Card>,
+        prepared_cards: Option<Vec<Card>>,
+        drawn_cards: Vec<Card>,
+    }
+
+    /// [`Savefile`] as it was saved before mode scripts were added
+    #[derive(Serialize)]
+    struct OldSavefile {
+        map: GameMap,
+        again_count: usize,
+        card_definitions: Vec<CardInitializationDefinition>,
+        round_registers: usize,
+        draw_cards: usize,
+        log_visibility: LogVisibility,
+        players: Vec<OldPlayer>,
+        store: ScriptStore,
+    }
+
+    fn map() -> GameMap {
+        GameMap {
+            name: "test".to_owned(),
+            tiles: Grid::new(vec![Tile::default(); 2], Position { x: 2, y: 1 }).unwrap(),
+            antenna: Position { x: 0, y: 0 },
+            reboot_token: (Position { x: 1, y: 0 }, Direction::Up),
+            checkpoints: vec![Position { x: 1, y: 0 }],
+            spawn_points: vec![(Position { x: 0, y: 0 }, Direction::Up)],
+            lasers: Vec::new(),
+            custom_tiles: Vec::new(),
+            script: None,
+        }
+    }
+
+    #[test]
+    fn savefile_without_mode_is_loaded() {
+        let old = OldSavefile {
+            map: map(),
+            again_count: 3,
+            card_definitions: Vec::new(),
+            round_registers: 1,
+            draw_cards: 1,
+            log_visibility: LogVisibility::Everyone,
+            players: vec![OldPlayer {
+                public_state: PlayerPublicState {
+                    position: Position { x: 1, y: 0 },
+                    direction: Direction::Up.to_continuous(),
+                    checkpoint: 1,
+                    is_rebooting: false,
+                    is_hidden: false,
+                },
+                draw_pile: vec![Card::Again],
+                hand: vec![Card::Again],
+                discard_pile: vec![Card::Again],
+                prepared_cards: None,
+                drawn_cards: vec![Card::SPAM],
+            }],
+            store: ScriptStore::new(1),
+        };
+        let savefile = Savefile::decode(&rmp_serde::to_vec(&old).unwrap()).unwrap();
+        assert_eq!(savefile.round_registers, 1);
+        assert_eq!(savefile.round, 0);
+        assert!(savefile.mode_script.is_none());
+        let player = &savefile.players[0];
+        assert_eq!(player.public_state.checkpoint, 1);
+        assert_eq!(player.drawn_cards, vec![Card::SPAM]);
+        assert_eq!(player.reboot_count, 0);
+    }
+
+    #[test]
+    fn current_savefile_is_loaded() {
+        let savefile = Savefile {
+            map: map(),
+            again_count: 3,
+            card_definitions: Vec::new(),
+            round_registers: 1,
+            draw_cards: 1,
+            log_visibility: LogVisibility::Everyone,
+            mode_script: Some("fn check_winner() {}".to_owned()),
+            round: 4,
+            players: vec![Player::new(
+                (Position { x: 0, y: 0 }, Direction::Up),
+                3,
+                &[],
+                1,
+            )],
+            store: ScriptStore::new(1),
+            chat_events: true,
+        };
+        let decoded = Savefile::decode(&rmp_serde::to_vec(&savefile).unwrap()).unwrap();
+        assert_eq!(decoded.round, 4);
+        assert!(decoded.mode_script.is_some());
+        assert!(decoded.chat_events);
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult};
+
+use crate::config::config;
+
+// card and map scripts come from arbitrary clients, so everything they can use up is limited
+// (the number of operations is in the server config)
+const MAX_STRING_SIZE: usize = 10_000;
+pub const MAX_ARRAY_SIZE: usize = 1000;
+const MAX_MAP_SIZE: usize = 1000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+/// Wall-clock budget of a single call into a script, e.g. one `execute` of a card
+const TIME_LIMIT: Duration = Duration::from_millis(200);
+/// How often (in operations) the time limit is checked, so that we don't read the clock all the time
+const TIME_CHECK_INTERVAL: u64 = 128;
+
+/// Deadline of the script call that is currently running on an engine
+///
+/// The engine checks it while running, see [`limit_engine`]
+#[derive(Default, Clone)]
+pub struct ScriptDeadline(Arc<Mutex<Option<Instant>>>);
+
+impl ScriptDeadline {
+    /// Runs a call into a script, aborting it once it runs longer than the time limit
+    pub fn run<T>(&self, call: impl FnOnce() -> T) -> T {
+        *self.0.lock().unwrap() = Some(Instant::now() + TIME_LIMIT);
+        let res = call();
+        *self.0.lock().unwrap() = None;
+        res
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() > deadline)
+    }
+}
+
+/// Sets all the limits on the engine
+///
+/// Modules don't need a limit, because the `import` statement is disabled altogether
+pub fn limit_engine(engine: &mut Engine) -> ScriptDeadline {
+    engine.set_max_operations(config().script_max_operations);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_ARRAY_SIZE);
+    engine.set_max_map_size(MAX_MAP_SIZE);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH);
+    let deadline = ScriptDeadline::default();
+    {
+        let deadline = deadline.clone();
+        engine.on_progress(move |ops| {
+            (ops % TIME_CHECK_INTERVAL == 0 && deadline.is_exceeded()).then_some(Dynamic::UNIT)
+        });
+    }
+    deadline
+}
+
+/// Human readable description of an error from a script, explaining which limit was hit if any
+pub fn describe_script_error(err: &EvalAltResult) -> String {
+    let mut inner = err;
+    while let EvalAltResult::ErrorInFunctionCall(_, _, e, _) = inner {
+        inner = e;
+    }
+    let limit = match inner {
+        EvalAltResult::ErrorTerminated(..) => format!(
+            "Script was stopped after running for longer than {} ms",
+            TIME_LIMIT.as_millis()
+        ),
+        EvalAltResult::ErrorTooManyOperations(_) => {
+            format!(
+                "Script was stopped after {} operations",
+                config().script_max_operations
+            )
+        }
+        EvalAltResult::ErrorStackOverflow(_) => {
+            format!("Functions are nested more than {MAX_CALL_LEVELS} levels deep")
+        }
+        EvalAltResult::ErrorDataTooLarge(what, _) => format!(
+            "{what} exceeds the limit (strings: {MAX_STRING_SIZE}, arrays: {MAX_ARRAY_SIZE}, maps: \
+             {MAX_MAP_SIZE})"
+        ),
+        _ => return err.to_string(),
+    };
+    let pos = inner.position();
+    if pos.is_none() {
+        limit
+    } else {
+        format!("{limit} ({pos})")
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use rhai::Dynamic;
+use serde::{Deserialize, Serialize};
+
+/// How many keys a single player (or the game) can have in the store
+const MAX_KEYS: usize = 100;
+
+/// Value that scripts can keep in the store, only simple values can be saved into a savefile
+#[derive(Serialize, Deserialize, Clone)]
+pub enum StoredValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl StoredValue {
+    fn from_dynamic(value: Dynamic) -> Result<Self, String> {
+        let type_name = value.type_name();
+        if let Some(b) = value.clone().try_cast::<bool>() {
+            Ok(Self::Bool(b))
+        } else if let Some(i) = value.clone().try_cast::<i64>() {
+            Ok(Self::Int(i))
+        } else if let Some(f) = value.clone().try_cast::<f64>() {
+            Ok(Self::Float(f))
+        } else if value.is_string() {
+            Ok(Self::String(value.into_string()?))
+        } else {
+            Err(format!(
+                "Only bools, numbers and strings can be stored, not {type_name}"
+            ))
+        }
+    }
+
+    fn to_dynamic(&self) -> Dynamic {
+        match self {
+            Self::Bool(b) => Dynamic::from_bool(*b),
+            Self::Int(i) => Dynamic::from_int(*i),
+            Self::Float(f) => Dynamic::from_float(*f),
+            Self::String(s) => s.clone().into(),
+        }
+    }
+}
+
+/// Key-value store for scripts, which survives across rounds
+///
+/// There's one namespace for the whole game, and one for each player
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ScriptStore {
+    game: BTreeMap<String, StoredValue>,
+    players: Vec<BTreeMap<String, StoredValue>>,
+}
+
+impl ScriptStore {
+    pub fn new(player_count: usize) -> Self {
+        Self {
+            game: BTreeMap::new(),
+            players: vec![BTreeMap::new(); player_count],
+        }
+    }
+
+    pub const fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// `()` if the key isn't set
+    pub fn get(&self, player_i: Option<usize>, key: &str) -> Dynamic {
+        self.namespace(player_i)
+            .and_then(|namespace| namespace.get(key))
+            .map_or(Dynamic::UNIT, StoredValue::to_dynamic)
+    }
+
+    /// Setting `()` removes the key
+    pub fn set(
+        &mut self,
+        player_i: Option<usize>,
+        key: &str,
+        value: Dynamic,
+    ) -> Result<(), String> {
+        let namespace = match player_i {
+            None => &mut self.game,
+            Some(player_i) => self
+                .players
+                .get_mut(player_i)
+                .ok_or_else(|| "There aren't that many players".to_owned())?,
+        };
+        if value.is_unit() {
+            namespace.remove(key);
+            return Ok(());
+        }
+        let value = StoredValue::from_dynamic(value)?;
+        if namespace.len() >= MAX_KEYS && !namespace.contains_key(key) {
+            return Err(format!("Only {MAX_KEYS} keys can be stored"));
+        }
+        namespace.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn namespace(&self, player_i: Option<usize>) -> Option<&BTreeMap<String, StoredValue>> {
+        player_i.map_or(Some(&self.game), |player_i| self.players.get(player_i))
+    }
+}
@@ -0,0 +1,155 @@
+use rhai::{EvalAltResult, ParseError, Position as ScriptPosition};
+use roborally_structs::{
+    game_log::LogEntry, game_map::GameMap, game_state::player_public_state::PlayerPublicState,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{CardInitializationDefinition, Game, NewGameData, MAX_CARD_DEFINITIONS},
+    script_limits::describe_script_error,
+};
+
+#[derive(Deserialize)]
+pub struct ValidateCardsData {
+    /// Map the cards are tried out on
+    pub map_name: String,
+    pub card_definitions: Vec<CardInitializationDefinition>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptErrorStage {
+    Compile,
+    Signature,
+    Run,
+}
+
+#[derive(Serialize)]
+pub struct ScriptError {
+    pub stage: ScriptErrorStage,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ScriptError {
+    fn new(stage: ScriptErrorStage, message: String, pos: ScriptPosition) -> Self {
+        Self {
+            stage,
+            message,
+            line: pos.line(),
+            column: pos.position(),
+        }
+    }
+
+    fn from_parse_error(ParseError(typ, pos): ParseError) -> Self {
+        Self::new(ScriptErrorStage::Compile, typ.to_string(), pos)
+    }
+
+    fn from_eval_error(mut err: Box<EvalAltResult>) -> Self {
+        // errors inside script functions are wrapped, the interesting position is the innermost one
+        while let EvalAltResult::ErrorInFunctionCall(_, _, inner, _) = *err {
+            err = inner;
+        }
+        let pos = err.take_position();
+        Self::new(ScriptErrorStage::Run, describe_script_error(&err), pos)
+    }
+}
+
+/// State of a player before and after the card was executed
+#[derive(Serialize)]
+pub struct PlayerChange {
+    pub before: PlayerPublicState,
+    pub after: PlayerPublicState,
+}
+
+#[derive(Serialize)]
+pub struct CardValidation {
+    pub name: String,
+    /// Empty if the card is fine
+    pub errors: Vec<ScriptError>,
+    /// One item for each player of the sandbox game, the card is executed by the first one.
+    /// Empty if the card couldn't be run
+    pub players: Vec<PlayerChange>,
+    /// What the card printed while running
+    pub log: Vec<LogEntry>,
+}
+
+/// Compiles every card and tries running it on a new game on given map
+///
+/// Every card is tried separately, on its own game, with up to 2 players. Each card can run for
+/// a while, so there can't be more of them than one game can use.
+pub fn validate_cards(
+    map: &GameMap,
+    card_definitions: Vec<CardInitializationDefinition>,
+) -> Result<Vec<CardValidation>, String> {
+    if card_definitions.len() > MAX_CARD_DEFINITIONS {
+        return Err(format!(
+            "At most {MAX_CARD_DEFINITIONS} cards can be validated at once"
+        ));
+    }
+    let player_count = map.spawn_points.len().min(2);
+    if player_count == 0 {
+        return Err("Map has no spawn points".to_owned());
+    }
+    let compiler = Game::new(
+        map.clone(),
+        NewGameData::sandbox(map.name.clone(), player_count, Vec::new()),
+    )?;
+    let settings = compiler.settings();
+    Ok(card_definitions
+        .into_iter()
+        .map(|def| {
+            let mut validation = CardValidation {
+                name: def.name.clone(),
+                errors: Vec::new(),
+                players: Vec::new(),
+                log: Vec::new(),
+            };
+            let ast = match compiler.compile_card(&def.code, def.name.clone(), settings) {
+                Ok((ast, _)) => ast,
+                Err(e) => {
+                    validation.errors.push(ScriptError::from_parse_error(e));
+                    return validation;
+                }
+            };
+            if !ast
+                .iter_functions()
+                .any(|f| f.name == "execute" && f.params.len() == 2)
+            {
+                validation.errors.push(ScriptError::new(
+                    ScriptErrorStage::Signature,
+                    "Card must define function execute(player_i, register_i)".to_owned(),
+                    ScriptPosition::NONE,
+                ));
+                return validation;
+            }
+            let game = match Game::new(
+                map.clone(),
+                NewGameData::sandbox(map.name.clone(), player_count, vec![def]),
+            ) {
+                Ok(g) => g,
+                Err(e) => {
+                    validation.errors.push(ScriptError::new(
+                        ScriptErrorStage::Run,
+                        e,
+                        ScriptPosition::NONE,
+                    ));
+                    return validation;
+                }
+            };
+            game.begin(&mut game.state.write().unwrap());
+            let (before, after, res) = game.dry_run_card(0);
+            if let Err(e) = res {
+                validation.errors.push(ScriptError::from_eval_error(e));
+            }
+            validation.players = before
+                .into_iter()
+                .zip(after)
+                .map(|(before, after)| PlayerChange { before, after })
+                .collect();
+            validation.log = game.log.take();
+            validation
+        })
+        .collect())
+}
@@ -3,7 +3,7 @@ use crate::position::{Direction, Position};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize))]
 pub enum Animation {
     BulletFlight {
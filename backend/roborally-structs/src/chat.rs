@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "client")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Longest chat message the server accepts, in characters
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 500;
+
+/// A message in the game chat, either from a player or about something that happened in the game
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
+pub struct ChatMessage {
+    /// Seat of the author, `None` for events posted by the server
+    pub seat: Option<usize>,
+    /// Name of the author at the time of writing, empty for events
+    pub author: String,
+    pub text: String,
+}
+
+#[cfg(feature = "client")]
+#[wasm_bindgen]
+impl ChatMessage {
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn seat(&self) -> Option<usize> {
+        self.seat
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn author(&self) -> String {
+        self.author.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub enum LogLevel {
+    /// Output of `print` in scripts
+    Info,
+    /// Output of `debug` in scripts
+    Debug,
+    Error,
+}
+
+/// One message of the game execution log
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct LogEntry {
+    pub level: LogLevel,
+    /// Name of the card whose script produced this entry
+    pub card: Option<String>,
+    /// Player the script was running for
+    pub player_i: Option<usize>,
+    pub register_i: Option<usize>,
+    pub message: String,
+}
+
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tags: Vec<String> = [
+            self.card.clone(),
+            self.player_i.map(|p| format!("player {p}")),
+            self.register_i.map(|r| format!("register {}", r + 1)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !tags.is_empty() {
+            write!(f, "[{}] ", tags.join(", "))?;
+        }
+        match self.level {
+            LogLevel::Info => {}
+            LogLevel::Debug => write!(f, "debug: ")?,
+            LogLevel::Error => write!(f, "error: ")?,
+        }
+        write!(f, "{}", self.message)
+    }
+}
@@ -15,6 +15,86 @@ pub struct GameMap {
     pub checkpoints: Vec<Position>,
     pub spawn_points: Vec<(Position, Direction)>,
     pub lasers: Vec<(Position, Direction)>,
+    /// Names of custom tile types used on this map
+    pub custom_tiles: Vec<String>,
+    /// Rhai script with hooks for custom tiles and other events
+    pub script: Option<String>,
+}
+
+impl GameMap {
+    /// Moves everything placed on the map to new positions, the tiles are replaced by the given ones
+    fn transformed(
+        &self,
+        tiles: Grid<Tile>,
+        transform_pos: impl Fn(Position) -> Position,
+        transform_dir: impl Fn(Direction) -> Direction,
+    ) -> Self {
+        let transform =
+            |(pos, dir): &(Position, Direction)| (transform_pos(*pos), transform_dir(*dir));
+        Self {
+            name: self.name.clone(),
+            tiles,
+            antenna: transform_pos(self.antenna),
+            reboot_token: transform(&self.reboot_token),
+            checkpoints: self
+                .checkpoints
+                .iter()
+                .map(|pos| transform_pos(*pos))
+                .collect(),
+            spawn_points: self.spawn_points.iter().map(transform).collect(),
+            lasers: self.lasers.iter().map(transform).collect(),
+            custom_tiles: self.custom_tiles.clone(),
+            script: self.script.clone(),
+        }
+    }
+
+    /// The whole map rotated by 90 degrees clockwise
+    #[must_use]
+    pub fn rotated(&self) -> Self {
+        let size = self.tiles.size();
+        self.transformed(
+            self.tiles.rotated(),
+            |pos| pos.rotated_in(size),
+            |dir| dir.rotated(),
+        )
+    }
+
+    /// Mirror image of the whole map, see [`Direction::mirrored`]
+    #[must_use]
+    pub fn mirrored(&self, horizontal: bool) -> Self {
+        let size = self.tiles.size();
+        self.transformed(
+            self.tiles.mirrored(horizontal),
+            |pos| pos.mirrored_in(size, horizontal),
+            |dir| dir.mirrored(horizontal),
+        )
+    }
+
+    /// The rectangle of given size, whose top left corner is at `origin`
+    ///
+    /// Spawn points and lasers outside of it are dropped, but the antenna, reboot token and all
+    /// checkpoints have to stay on the map.
+    pub fn cropped(&self, origin: Position, size: Position) -> Result<Self, String> {
+        let mut map = self.transformed(
+            self.tiles.cropped(origin, size)?,
+            |pos| Position {
+                x: pos.x - origin.x,
+                y: pos.y - origin.y,
+            },
+            |dir| dir,
+        );
+        if !size.contains(map.antenna)
+            || !size.contains(map.reboot_token.0)
+            || !map.checkpoints.iter().all(|pos| size.contains(*pos))
+        {
+            return Err(
+                "Antenna, reboot token and all checkpoints must be inside the crop area".to_owned(),
+            );
+        }
+        map.spawn_points.retain(|(pos, _)| size.contains(*pos));
+        map.lasers.retain(|(pos, _)| size.contains(*pos));
+        Ok(map)
+    }
 }
 
 impl std::fmt::Debug for GameMap {
@@ -26,3 +106,100 @@ impl std::fmt::Debug for GameMap {
             .finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x2 map of floor, with everything placed in different places
+    fn map() -> GameMap {
+        let size = Position { x: 3, y: 2 };
+        GameMap {
+            name: "test".to_owned(),
+            tiles: Grid::new(vec![Tile::default(); 6], size).unwrap(),
+            antenna: Position { x: 0, y: 0 },
+            reboot_token: (Position { x: 1, y: 0 }, Direction::Right),
+            checkpoints: vec![Position { x: 2, y: 1 }],
+            spawn_points: vec![(Position { x: 0, y: 1 }, Direction::Up)],
+            lasers: vec![(Position { x: 2, y: 0 }, Direction::Left)],
+            custom_tiles: Vec::new(),
+            script: None,
+        }
+    }
+
+    #[test]
+    fn rotated_moves_everything() {
+        let rotated = map().rotated();
+        assert_eq!(rotated.tiles.size(), Position { x: 2, y: 3 });
+        assert_eq!(rotated.antenna, Position { x: 1, y: 0 });
+        assert_eq!(
+            rotated.reboot_token,
+            (Position { x: 1, y: 1 }, Direction::Down)
+        );
+        assert_eq!(rotated.checkpoints, vec![Position { x: 0, y: 2 }]);
+        assert_eq!(
+            rotated.spawn_points,
+            vec![(Position { x: 0, y: 0 }, Direction::Right)]
+        );
+        assert_eq!(
+            rotated.lasers,
+            vec![(Position { x: 1, y: 2 }, Direction::Up)]
+        );
+    }
+
+    #[test]
+    fn rotated_four_times_is_identity() {
+        let original = map();
+        let rotated = original.rotated().rotated().rotated().rotated();
+        assert_eq!(rotated.tiles.size(), original.tiles.size());
+        assert_eq!(rotated.antenna, original.antenna);
+        assert_eq!(rotated.reboot_token, original.reboot_token);
+        assert_eq!(rotated.checkpoints, original.checkpoints);
+        assert_eq!(rotated.spawn_points, original.spawn_points);
+        assert_eq!(rotated.lasers, original.lasers);
+    }
+
+    #[test]
+    fn mirrored_moves_everything() {
+        let horizontal = map().mirrored(true);
+        assert_eq!(horizontal.antenna, Position { x: 2, y: 0 });
+        assert_eq!(
+            horizontal.reboot_token,
+            (Position { x: 1, y: 0 }, Direction::Left)
+        );
+        assert_eq!(
+            horizontal.spawn_points,
+            vec![(Position { x: 2, y: 1 }, Direction::Up)]
+        );
+
+        let vertical = map().mirrored(false);
+        assert_eq!(vertical.antenna, Position { x: 0, y: 1 });
+        assert_eq!(vertical.checkpoints, vec![Position { x: 2, y: 0 }]);
+        assert_eq!(
+            vertical.spawn_points,
+            vec![(Position { x: 0, y: 0 }, Direction::Down)]
+        );
+    }
+
+    #[test]
+    fn cropped_drops_outside_spawns_and_lasers() {
+        let mut original = map();
+        // the checkpoint would be cut off
+        assert!(original
+            .cropped(Position { x: 0, y: 0 }, Position { x: 3, y: 1 })
+            .is_err());
+        original.checkpoints = vec![Position { x: 1, y: 0 }];
+        // the antenna would be cut off
+        assert!(original
+            .cropped(Position { x: 1, y: 0 }, Position { x: 2, y: 2 })
+            .is_err());
+
+        let cropped = original
+            .cropped(Position { x: 0, y: 0 }, Position { x: 2, y: 1 })
+            .unwrap();
+        assert_eq!(cropped.tiles.size(), Position { x: 2, y: 1 });
+        assert!(cropped.spawn_points.is_empty());
+        assert!(cropped.lasers.is_empty());
+        assert_eq!(cropped.checkpoints, vec![Position { x: 1, y: 0 }]);
+    }
+}
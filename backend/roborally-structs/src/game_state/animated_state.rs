@@ -1,4 +1,9 @@
-use crate::{animations::Animation, card::Card, create_array_type};
+use crate::{
+    animations::Animation,
+    card::Card,
+    create_array_type,
+    position::{ContinuousDirection, Position},
+};
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "client")]
@@ -10,7 +15,7 @@ use wasm_bindgen::{
 use super::{phase::RegisterMovePhase, player_public_state::PlayerPublicState};
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
 #[allow(clippy::unsafe_derive_deserialize)]
 /// Player's view of the game - doesn't inlude other players' cards etc.
@@ -26,6 +31,117 @@ pub struct RunningStateView {
     pub player_states: Vec<PlayerPublicState>,
 }
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+/// Fields of a [`PlayerPublicState`] that changed, `None` for those that didn't
+pub struct PlayerStateDelta {
+    pub player_i: usize,
+    pub position: Option<Position>,
+    pub direction: Option<ContinuousDirection>,
+    pub checkpoint: Option<usize>,
+    pub is_rebooting: Option<bool>,
+    pub is_hidden: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+/// Changes since the previous [`RunningStateView`] sent to the same player
+pub struct RunningStateDelta {
+    pub register: usize,
+
+    pub register_phase: RegisterMovePhase,
+
+    /// `None` if the cards didn't change
+    pub my_cards: Option<Vec<Card>>,
+
+    /// Players whose revealed cards changed, along with all their revealed cards
+    pub players_revealed_cards: Vec<(usize, Vec<Card>)>,
+
+    /// Only players whose state changed
+    pub player_states: Vec<PlayerStateDelta>,
+}
+
+#[cfg(feature = "server")]
+impl RunningStateDelta {
+    #[must_use]
+    pub fn between(previous: &RunningStateView, next: &RunningStateView) -> Self {
+        Self {
+            register: next.register,
+            register_phase: next.register_phase,
+            my_cards: (previous.my_cards != next.my_cards).then(|| next.my_cards.clone()),
+            players_revealed_cards: next
+                .players_revealed_cards
+                .iter()
+                .enumerate()
+                .filter(|(i, cards)| previous.players_revealed_cards.get(*i) != Some(*cards))
+                .map(|(i, cards)| (i, cards.clone()))
+                .collect(),
+            player_states: next
+                .player_states
+                .iter()
+                .zip(&previous.player_states)
+                .enumerate()
+                .filter_map(|(player_i, (after, before))| {
+                    let delta = PlayerStateDelta {
+                        player_i,
+                        position: (after.position != before.position).then_some(after.position),
+                        direction: (!after.direction.is_identical(&before.direction))
+                            .then_some(after.direction),
+                        checkpoint: (after.checkpoint != before.checkpoint)
+                            .then_some(after.checkpoint),
+                        is_rebooting: (after.is_rebooting != before.is_rebooting)
+                            .then_some(after.is_rebooting),
+                        is_hidden: (after.is_hidden != before.is_hidden).then_some(after.is_hidden),
+                    };
+                    (delta.position.is_some()
+                        || delta.direction.is_some()
+                        || delta.checkpoint.is_some()
+                        || delta.is_rebooting.is_some()
+                        || delta.is_hidden.is_some())
+                    .then_some(delta)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(any(feature = "client", test))]
+impl RunningStateView {
+    #[must_use]
+    pub fn with_delta(&self, delta: &RunningStateDelta) -> Self {
+        let mut state = self.clone();
+        state.register = delta.register;
+        state.register_phase = delta.register_phase;
+        if let Some(my_cards) = &delta.my_cards {
+            state.my_cards.clone_from(my_cards);
+        }
+        for (player_i, cards) in &delta.players_revealed_cards {
+            state.players_revealed_cards[*player_i].clone_from(cards);
+        }
+        for change in &delta.player_states {
+            let player = &mut state.player_states[change.player_i];
+            if let Some(position) = change.position {
+                player.position = position;
+            }
+            if let Some(direction) = change.direction {
+                player.direction = direction;
+            }
+            if let Some(checkpoint) = change.checkpoint {
+                player.checkpoint = checkpoint;
+            }
+            if let Some(is_rebooting) = change.is_rebooting {
+                player.is_rebooting = is_rebooting;
+            }
+            if let Some(is_hidden) = change.is_hidden {
+                player.is_hidden = is_hidden;
+            }
+        }
+        state
+    }
+}
+
 #[cfg(feature = "client")]
 #[wasm_bindgen]
 extern "C" {
@@ -42,7 +158,7 @@ extern "C" {
 }
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
 #[allow(clippy::unsafe_derive_deserialize)]
 pub struct AnimationItem {
@@ -51,6 +167,29 @@ pub struct AnimationItem {
     pub state: Option<RunningStateView>,
 }
 
+/// Like [`AnimationItem`], but the state is relative to the previous one
+///
+/// Only used within a register, each register starts with a full [`AnimationItem`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct AnimationDelta {
+    pub animations: Vec<Animation>,
+    pub state: RunningStateDelta,
+}
+
+#[cfg(any(feature = "client", test))]
+impl AnimationDelta {
+    /// Creates the full item, `previous` has to be the last item with a state
+    #[must_use]
+    pub fn apply(&self, previous: &AnimationItem) -> AnimationItem {
+        AnimationItem {
+            animations: self.animations.clone(),
+            state: Some(previous.state.as_ref().unwrap().with_delta(&self.state)),
+        }
+    }
+}
+
 #[cfg(feature = "client")]
 create_array_type!(name: AnimationItemArray, full_js_type: "Array<AnimationItem>", rust_inner_type: AnimationItem);
 
@@ -154,3 +293,95 @@ impl AnimationItem {
             .collect()
     }
 }
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::position::Direction;
+
+    fn player(x: i16, direction: Direction) -> PlayerPublicState {
+        PlayerPublicState {
+            position: Position { x, y: 0 },
+            direction: direction.to_continuous(),
+            checkpoint: 0,
+            is_rebooting: false,
+            is_hidden: false,
+        }
+    }
+
+    fn state() -> RunningStateView {
+        RunningStateView {
+            register: 0,
+            register_phase: RegisterMovePhase::PlayerCards,
+            my_cards: vec![Card::Custom(0), Card::Again],
+            players_revealed_cards: vec![vec![Card::Custom(0)], vec![Card::SPAM]],
+            player_states: vec![player(0, Direction::Up), player(1, Direction::Left)],
+        }
+    }
+
+    /// Applies the delta between the states to the first one and compares the result with the second one
+    fn assert_round_trip(previous: &RunningStateView, next: &RunningStateView) {
+        let delta = AnimationDelta {
+            animations: vec![Animation::CheckpointVisited { player_i: 0 }],
+            state: RunningStateDelta::between(previous, next),
+        };
+        let previous_item = AnimationItem {
+            animations: Vec::new(),
+            state: Some(previous.clone()),
+        };
+        let expected = AnimationItem {
+            animations: delta.animations.clone(),
+            state: Some(next.clone()),
+        };
+        assert_eq!(
+            serde_json::to_value(delta.apply(&previous_item)).unwrap(),
+            serde_json::to_value(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn unchanged_state_has_empty_delta() {
+        let delta = RunningStateDelta::between(&state(), &state());
+        assert!(delta.my_cards.is_none());
+        assert!(delta.players_revealed_cards.is_empty());
+        assert!(delta.player_states.is_empty());
+        assert_round_trip(&state(), &state());
+    }
+
+    #[test]
+    fn delta_applies_to_next_state() {
+        let previous = state();
+        let mut next = state();
+        next.register = 1;
+        next.register_phase = RegisterMovePhase::Lasers;
+        next.my_cards = vec![Card::SPAM];
+        next.players_revealed_cards[1].push(Card::Again);
+        next.player_states[1].position = Position { x: 5, y: 3 };
+        next.player_states[1].checkpoint = 2;
+        next.player_states[1].is_rebooting = true;
+        next.player_states[1].is_hidden = true;
+
+        let delta = RunningStateDelta::between(&previous, &next);
+        assert!(delta.my_cards.is_some());
+        assert_eq!(delta.players_revealed_cards.len(), 1);
+        assert_eq!(delta.player_states.len(), 1);
+        assert_eq!(delta.player_states[0].player_i, 1);
+        assert_round_trip(&previous, &next);
+    }
+
+    #[test]
+    fn full_rotation_is_kept_in_delta() {
+        let previous = state();
+        let mut next = state();
+        // rotated by 360 degrees, which is a different continuous direction
+        next.player_states[0].direction = previous.player_states[0]
+            .direction
+            .rotated()
+            .rotated()
+            .rotated()
+            .rotated();
+        let delta = RunningStateDelta::between(&previous, &next);
+        assert!(delta.player_states[0].direction.is_some());
+        assert_round_trip(&previous, &next);
+    }
+}
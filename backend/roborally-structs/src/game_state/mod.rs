@@ -11,11 +11,15 @@ use wasm_bindgen::prelude::wasm_bindgen;
 use self::player_public_state::PlayerPublicState;
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize))]
 pub enum GameStatusInfo {
     Programming,
     Processing,
+    /// Seats of the winners
+    Finished(Vec<usize>),
+    /// Players are getting ready, the host hasn't started the game yet
+    Lobby,
 }
 
 impl std::fmt::Display for GameStatusInfo {
@@ -23,18 +27,24 @@ impl std::fmt::Display for GameStatusInfo {
         match self {
             GameStatusInfo::Programming => write!(f, "Waiting for players to program their robots"),
             GameStatusInfo::Processing => write!(f, "Evaluating moves"),
+            GameStatusInfo::Finished(_) => write!(f, "Game over"),
+            GameStatusInfo::Lobby => write!(f, "Waiting for the host to start the game"),
         }
     }
 }
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
 #[allow(clippy::unsafe_derive_deserialize)]
 /// Player's view of the game - doesn't inlude other players' cards etc.
 pub struct GeneralState {
     pub player_names: Vec<Option<String>>,
     pub status: GameStatusInfo,
+    /// Seat of the player who can moderate the game
+    pub host: Option<usize>,
+    /// The round stops before the next register until the host resumes it
+    pub paused: bool,
 }
 
 #[cfg(feature = "client")]
@@ -50,10 +60,38 @@ impl GeneralState {
     pub fn status(&self) -> String {
         self.status.to_string()
     }
+
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn in_lobby(&self) -> bool {
+        matches!(self.status, GameStatusInfo::Lobby)
+    }
+
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn host(&self) -> Option<usize> {
+        self.host
+    }
+
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Seats of the players who won, empty while the game is running
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn winners(&self) -> Vec<usize> {
+        match &self.status {
+            GameStatusInfo::Finished(winners) => winners.clone(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
 #[allow(clippy::unsafe_derive_deserialize)]
 pub struct ProgrammingState {
@@ -92,3 +130,58 @@ impl ProgrammingState {
         self.player_states.clone().into_iter().collect()
     }
 }
+
+/// Settings that the host can change in the lobby
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(schemars::JsonSchema))]
+pub struct RoundSettings {
+    pub round_registers: usize,
+    pub draw_cards: usize,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen(skip_all))]
+#[allow(clippy::unsafe_derive_deserialize)]
+/// State of the game before it starts, player names are in [`GeneralState`]
+pub struct LobbyState {
+    /// Seat of the player receiving this, it changes when they switch seats
+    pub seat: usize,
+    pub host: Option<usize>,
+    pub ready_players: Vec<bool>,
+    pub settings: RoundSettings,
+}
+
+#[cfg(feature = "client")]
+#[wasm_bindgen]
+impl LobbyState {
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn seat(&self) -> usize {
+        self.seat
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn host(&self) -> Option<usize> {
+        self.host
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn ready_players(&self) -> Vec<u8> {
+        self.ready_players.iter().map(|b| u8::from(*b)).collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn round_registers(&self) -> usize {
+        self.settings.round_registers
+    }
+
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn draw_cards(&self) -> usize {
+        self.settings.draw_cards
+    }
+}
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen)]
 pub enum RegisterMovePhase {
     PlayerCards,
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 #[derive(Clone, Debug, Deserialize)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", wasm_bindgen(skip_all))]
 #[allow(clippy::unsafe_derive_deserialize)]
 /// Public state of 1 player
@@ -35,6 +35,8 @@
 
 pub mod animations;
 pub mod card;
+pub mod chat;
+pub mod game_log;
 pub mod game_map;
 pub mod game_state;
 pub mod logging;
@@ -2,17 +2,23 @@ use std::thread;
 
 use log::Record;
 pub use log::{error, info, warn};
+#[cfg(all(not(feature = "client"), feature = "server"))]
+pub use platform::{LogFilter, LogFormat};
 
-fn format(record: &Record) -> String {
+fn thread_name() -> String {
     let cur_thread = thread::current();
+    cur_thread.name().map_or_else(
+        || cur_thread.id().as_u64().to_string(),
+        |n| format!("{n}:{:0>2}", cur_thread.id().as_u64()),
+    )
+}
+
+fn format(record: &Record) -> String {
     format!(
         "[{:0<18}] {}({}) {}@{}: {}",
         platform::get_time(),
         record.level(),
-        cur_thread.name().map_or_else(
-            || cur_thread.id().as_u64().to_string(),
-            |n| format!("{n}:{:0>2}", cur_thread.id().as_u64())
-        ),
+        thread_name(),
         record.module_path().unwrap_or("<unknown module>"),
         record
             .line()
@@ -62,27 +68,184 @@ mod platform {
 
 #[cfg(all(not(feature = "client"), feature = "server"))]
 mod platform {
-    use log::{set_max_level, LevelFilter, Log, Metadata, Record, SetLoggerError};
-    pub struct Logger;
-    static LOGGER: Logger = Logger;
+    use std::{fmt::Write, str::FromStr, sync::OnceLock};
+
+    use log::{
+        kv::{self, VisitSource},
+        set_max_level, LevelFilter, Log, Metadata, Record, SetLoggerError,
+    };
+    use serde::Deserialize;
+    use serde_json::{Map, Number, Value};
+
+    /// How records are written to stderr
+    #[derive(Deserialize, Clone, Copy, Debug, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum LogFormat {
+        /// Human-readable lines, with the record's fields appended as `key=value`
+        #[default]
+        Text,
+        /// One JSON object per line, the record's fields are top-level keys
+        Json,
+    }
+
+    impl FromStr for LogFormat {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "text" => Ok(Self::Text),
+                "json" => Ok(Self::Json),
+                _ => Err(format!("Unknown log format {s}, expected text or json")),
+            }
+        }
+    }
+
+    /// Maximum levels of records, written as e.g. `info,roborally_server::maps=debug,rhai=off`
+    ///
+    /// The level without a module applies to modules not listed (debug if not given), and the
+    /// longest matching module prefix wins
+    #[derive(Deserialize, Clone, Debug)]
+    #[serde(try_from = "String")]
+    pub struct LogFilter {
+        default: LevelFilter,
+        modules: Vec<(String, LevelFilter)>,
+    }
+
+    impl LogFilter {
+        fn level_for(&self, target: &str) -> LevelFilter {
+            self.modules
+                .iter()
+                .filter(|(module, _)| {
+                    target
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+                })
+                .max_by_key(|(module, _)| module.len())
+                .map_or(self.default, |(_, level)| *level)
+        }
+
+        fn max_level(&self) -> LevelFilter {
+            (self.modules.iter().map(|(_, level)| *level)).fold(self.default, Ord::max)
+        }
+    }
+
+    impl Default for LogFilter {
+        fn default() -> Self {
+            Self {
+                default: LevelFilter::Debug,
+                modules: Vec::new(),
+            }
+        }
+    }
+
+    impl FromStr for LogFilter {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let parse_level = |level: &str| {
+                LevelFilter::from_str(level).map_err(|_| format!("Unknown log level {level}"))
+            };
+            let mut filter = Self::default();
+            for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+                match directive.split_once('=') {
+                    Some((module, level)) => {
+                        filter
+                            .modules
+                            .push((module.to_owned(), parse_level(level)?));
+                    }
+                    None => filter.default = parse_level(directive)?,
+                }
+            }
+            Ok(filter)
+        }
+    }
+
+    impl TryFrom<String> for LogFilter {
+        type Error = String;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            s.parse()
+        }
+    }
+
+    /// Collects the key-values of a record, e.g. `info!(game = name, seat = 1; "...")`
+    #[derive(Default)]
+    struct Fields(Vec<(String, Value)>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            value: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let json = (value.to_u64().map(Value::from))
+                .or_else(|| value.to_i64().map(Value::from))
+                .or_else(|| value.to_bool().map(Value::from))
+                .or_else(|| value.to_f64().and_then(Number::from_f64).map(Value::Number))
+                .unwrap_or_else(|| Value::String(value.to_string()));
+            self.0.push((key.to_string(), json));
+            Ok(())
+        }
+    }
+
+    fn fields(record: &Record) -> Fields {
+        let mut fields = Fields::default();
+        // the visitor never fails
+        record.key_values().visit(&mut fields).unwrap();
+        fields
+    }
+
+    fn format_json(record: &Record) -> String {
+        let mut line = Map::new();
+        line.insert("time".to_owned(), Number::from_f64(get_time()).into());
+        line.insert("level".to_owned(), record.level().as_str().into());
+        line.insert("thread".to_owned(), super::thread_name().into());
+        line.insert("module".to_owned(), record.module_path().into());
+        line.insert("line".to_owned(), record.line().into());
+        line.insert("message".to_owned(), record.args().to_string().into());
+        line.extend(fields(record).0);
+        Value::Object(line).to_string()
+    }
+
+    fn format_text(record: &Record) -> String {
+        let mut line = super::format(record);
+        for (key, value) in fields(record).0 {
+            match value {
+                Value::String(s) => write!(line, " {key}={s:?}").unwrap(),
+                v => write!(line, " {key}={v}").unwrap(),
+            }
+        }
+        line
+    }
+
+    pub struct Logger {
+        filter: LogFilter,
+        format: LogFormat,
+    }
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
 
     impl Log for Logger {
-        fn enabled(&self, _metadata: &Metadata) -> bool {
-            true
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.filter.level_for(metadata.target())
         }
 
         fn log(&self, record: &Record) {
             if !self.enabled(record.metadata()) {
                 return;
             }
-            eprintln!("{}", super::format(record));
+            match self.format {
+                LogFormat::Text => eprintln!("{}", format_text(record)),
+                LogFormat::Json => eprintln!("{}", format_json(record)),
+            }
         }
 
         fn flush(&self) {}
     }
 
-    pub fn init() -> Result<(), SetLoggerError> {
-        log::set_logger(&LOGGER).map(|()| set_max_level(LevelFilter::Debug))
+    pub fn init(filter: LogFilter, format: LogFormat) -> Result<(), SetLoggerError> {
+        let max_level = filter.max_level();
+        let logger = LOGGER.get_or_init(|| Logger { filter, format });
+        log::set_logger(logger).map(|()| set_max_level(max_level))
     }
 
     pub fn get_time() -> f64 {
@@ -92,14 +255,73 @@ mod platform {
             .as_nanos() as f64
             / 1_000_000_000.0
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn empty_filter_is_debug() {
+            let filter: LogFilter = "".parse().unwrap();
+            assert_eq!(filter.level_for("roborally_server"), LevelFilter::Debug);
+            assert_eq!(filter.max_level(), LevelFilter::Debug);
+        }
+
+        #[test]
+        fn longest_module_prefix_wins() {
+            let filter: LogFilter =
+                "warn, roborally_server=info,roborally_server::maps=trace,rhai=off"
+                    .parse()
+                    .unwrap();
+            assert_eq!(filter.level_for("tokio"), LevelFilter::Warn);
+            assert_eq!(filter.level_for("roborally_server"), LevelFilter::Info);
+            assert_eq!(
+                filter.level_for("roborally_server::game"),
+                LevelFilter::Info
+            );
+            assert_eq!(
+                filter.level_for("roborally_server::maps::parser"),
+                LevelFilter::Trace
+            );
+            assert_eq!(filter.level_for("rhai::engine"), LevelFilter::Off);
+            assert_eq!(filter.max_level(), LevelFilter::Trace);
+        }
+
+        #[test]
+        fn module_matches_whole_path_segments() {
+            let filter: LogFilter = "error,roborally=debug".parse().unwrap();
+            assert_eq!(filter.level_for("roborally"), LevelFilter::Debug);
+            assert_eq!(filter.level_for("roborally::x"), LevelFilter::Debug);
+            assert_eq!(filter.level_for("roborally_server"), LevelFilter::Error);
+        }
+
+        #[test]
+        fn unknown_level_is_rejected() {
+            assert!("loud".parse::<LogFilter>().is_err());
+            assert!("info,rhai=loud".parse::<LogFilter>().is_err());
+        }
+    }
+}
+
+fn report_init_error(e: &log::SetLoggerError) {
+    let msg = format!("Error setting up logging: {e}");
+    #[cfg(feature = "server")]
+    eprintln!("{}", &msg);
+    #[cfg(feature = "client")]
+    web_sys::console::error_1(&msg.into());
 }
 
+#[cfg(feature = "client")]
 pub fn init() {
     if let Err(e) = platform::init() {
-        let msg = format!("Error setting up logging: {e}");
-        #[cfg(feature = "server")]
-        eprintln!("{}", &msg);
-        #[cfg(feature = "client")]
-        web_sys::console::error_1(&msg.into());
+        report_init_error(&e);
+    }
+}
+
+/// Sets up logging to stderr, records above the filter's levels are dropped
+#[cfg(all(not(feature = "client"), feature = "server"))]
+pub fn init(filter: LogFilter, format: LogFormat) {
+    if let Err(e) = platform::init(filter, format) {
+        report_init_error(&e);
     }
 }
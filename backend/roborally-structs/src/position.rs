@@ -10,7 +10,7 @@ use wasm_bindgen::prelude::wasm_bindgen;
 /// &darr;  \
 /// +y
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", wasm_bindgen)]
 pub struct Position {
     pub x: i16,
@@ -25,6 +25,33 @@ impl Position {
         0 <= other.x && other.x < self.x && 0 <= other.y && other.y < self.y
     }
 
+    #[inline]
+    #[must_use]
+    /// Returns where this position ends up when the rectangle [origin, size) is rotated by 90 degrees clockwise
+    pub const fn rotated_in(self, size: Self) -> Self {
+        Self {
+            x: size.y - 1 - self.y,
+            y: self.x,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    /// Returns where this position ends up when the rectangle [origin, size) is mirrored, see [`Direction::mirrored`]
+    pub const fn mirrored_in(self, size: Self, horizontal: bool) -> Self {
+        if horizontal {
+            Self {
+                x: size.x - 1 - self.x,
+                y: self.y,
+            }
+        } else {
+            Self {
+                x: self.x,
+                y: size.y - 1 - self.y,
+            }
+        }
+    }
+
     #[inline]
     #[must_use]
     /// Returns a new position moved by one tile in given direction
@@ -85,7 +112,7 @@ impl PartialOrd for Priority {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", wasm_bindgen)]
 pub enum Direction {
     Up,
@@ -129,10 +156,24 @@ impl Direction {
             Right => Up,
         }
     }
+
+    /// Horizontal mirroring swaps left and right, vertical swaps up and down
+    #[inline]
+    #[must_use]
+    pub const fn mirrored(&self, horizontal: bool) -> Direction {
+        use Direction::*;
+        match (self, horizontal) {
+            (Left, true) => Right,
+            (Right, true) => Left,
+            (Up, false) => Down,
+            (Down, false) => Up,
+            (dir, _) => *dir,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize)]
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 /// A direction that can continuously rotate by more that 270 degrees in one direction
 ///
 /// While rotation by 360 degrees equals no rotation, CSS used to rotate robots weirdly
@@ -140,6 +181,13 @@ impl Direction {
 pub struct ContinuousDirection(i64);
 
 impl ContinuousDirection {
+    /// Unlike `==`, this distinguishes directions rotated by a multiple of 360 degrees
+    #[inline]
+    #[must_use]
+    pub const fn is_identical(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+
     #[inline]
     #[must_use]
     pub const fn is_none(&self) -> bool {
@@ -25,6 +25,35 @@ impl DirectionBools {
         }
     }
 
+    /// Walls of the same tile after rotating it by 90 degrees clockwise
+    #[must_use]
+    pub const fn rotated(&self) -> Self {
+        Self {
+            up: self.left,
+            right: self.up,
+            down: self.right,
+            left: self.down,
+        }
+    }
+
+    /// Walls of the same tile after mirroring it, see [`Direction::mirrored`]
+    #[must_use]
+    pub const fn mirrored(&self, horizontal: bool) -> Self {
+        if horizontal {
+            Self {
+                right: self.left,
+                left: self.right,
+                ..*self
+            }
+        } else {
+            Self {
+                up: self.down,
+                down: self.up,
+                ..*self
+            }
+        }
+    }
+
     #[must_use]
     pub const fn to_items(&self) -> [(Direction, bool); 4] {
         [
@@ -36,13 +65,33 @@ impl DirectionBools {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
 #[cfg_attr(feature = "server", derive(Serialize))]
 pub struct Tile {
     pub typ: TileType,
     pub walls: DirectionBools,
 }
 
+impl Tile {
+    /// The same tile rotated by 90 degrees clockwise
+    #[must_use]
+    pub const fn rotated(&self) -> Self {
+        Self {
+            typ: self.typ.rotated(),
+            walls: self.walls.rotated(),
+        }
+    }
+
+    /// The mirror image of this tile, see [`Direction::mirrored`]
+    #[must_use]
+    pub const fn mirrored(&self, horizontal: bool) -> Self {
+        Self {
+            typ: self.typ.mirrored(horizontal),
+            walls: self.walls.mirrored(horizontal),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[cfg_attr(feature = "server", derive(Serialize))]
 pub struct Grid<T> {
@@ -77,6 +126,79 @@ impl<T> Grid<T> {
         &self.vec
     }
 
+    /// Rearranges the items as if the grid was rotated by 90 degrees clockwise
+    ///
+    /// The items themselves aren't changed, see `Grid<Tile>::rotated` for that
+    #[must_use]
+    pub fn rotated_positions(&self) -> Self
+    where
+        T: Clone,
+    {
+        let new_size = Position {
+            x: self.size.y,
+            y: self.size.x,
+        };
+        let vec = (0..new_size.y)
+            .flat_map(|y| (0..new_size.x).map(move |x| Position { x, y }))
+            .map(|new_pos| {
+                // inverse of Position::rotated_in
+                self.get(Position {
+                    x: new_pos.y,
+                    y: self.size.y - 1 - new_pos.x,
+                })
+                .unwrap()
+                .clone()
+            })
+            .collect();
+        Self {
+            vec,
+            size: new_size,
+        }
+    }
+
+    /// Rearranges the items as if the grid was mirrored, see [`Direction::mirrored`]
+    ///
+    /// The items themselves aren't changed, see `Grid<Tile>::mirrored` for that
+    #[must_use]
+    pub fn mirrored_positions(&self, horizontal: bool) -> Self
+    where
+        T: Clone,
+    {
+        let vec = (0..self.size.y)
+            .flat_map(|y| (0..self.size.x).map(move |x| Position { x, y }))
+            .map(|pos| {
+                self.get(pos.mirrored_in(self.size, horizontal))
+                    .unwrap()
+                    .clone()
+            })
+            .collect();
+        Self {
+            vec,
+            size: self.size,
+        }
+    }
+
+    /// The rectangle of given size, whose top left corner is at `origin`
+    pub fn cropped(&self, origin: Position, size: Position) -> Result<Self, String>
+    where
+        T: Clone,
+    {
+        if origin.x < 0
+            || origin.y < 0
+            || size.x <= 0
+            || size.y <= 0
+            || origin.x + size.x > self.size.x
+            || origin.y + size.y > self.size.y
+        {
+            return Err("Crop area must be non-empty and inside the grid".to_owned());
+        }
+        let vec = (origin.y..origin.y + size.y)
+            .flat_map(|y| (origin.x..origin.x + size.x).map(move |x| Position { x, y }))
+            .map(|pos| self.get(pos).unwrap().clone())
+            .collect();
+        Ok(Self { vec, size })
+    }
+
     pub fn new(vec: Vec<T>, size: Position) -> Result<Self, String> {
         if (size.x * size.y) as usize == vec.len() {
             Ok(Self { vec, size })
@@ -85,3 +207,119 @@ impl<T> Grid<T> {
         }
     }
 }
+
+impl Grid<Tile> {
+    /// The tiles rotated by 90 degrees clockwise, including walls and directions of all tiles
+    #[must_use]
+    pub fn rotated(&self) -> Self {
+        let mut grid = self.rotated_positions();
+        for tile in &mut grid.vec {
+            *tile = tile.rotated();
+        }
+        grid
+    }
+
+    /// The mirror image of the tiles, including walls and directions of all tiles
+    #[must_use]
+    pub fn mirrored(&self, horizontal: bool) -> Self {
+        let mut grid = self.mirrored_positions(horizontal);
+        for tile in &mut grid.vec {
+            *tile = tile.mirrored(horizontal);
+        }
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x2 grid whose items are their original positions
+    fn positions_grid() -> Grid<Position> {
+        let size = Position { x: 3, y: 2 };
+        let vec = (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| Position { x, y }))
+            .collect();
+        Grid::new(vec, size).unwrap()
+    }
+
+    #[test]
+    fn rotated_positions_match_position_rotation() {
+        let grid = positions_grid();
+        let rotated = grid.rotated_positions();
+        assert_eq!(rotated.size(), Position { x: 2, y: 3 });
+        for original in grid.vec() {
+            let new_pos = original.rotated_in(grid.size());
+            assert_eq!(rotated.get(new_pos), Some(original));
+        }
+    }
+
+    #[test]
+    fn mirrored_positions_match_position_mirroring() {
+        let grid = positions_grid();
+        for horizontal in [true, false] {
+            let mirrored = grid.mirrored_positions(horizontal);
+            assert_eq!(mirrored.size(), grid.size());
+            for original in grid.vec() {
+                let new_pos = original.mirrored_in(grid.size(), horizontal);
+                assert_eq!(mirrored.get(new_pos), Some(original));
+            }
+        }
+    }
+
+    #[test]
+    fn cropped_keeps_the_area() {
+        let grid = positions_grid();
+        let cropped = grid
+            .cropped(Position { x: 1, y: 1 }, Position { x: 2, y: 1 })
+            .unwrap();
+        assert_eq!(
+            cropped.vec(),
+            &vec![Position { x: 1, y: 1 }, Position { x: 2, y: 1 }]
+        );
+        assert!(grid
+            .cropped(Position { x: 2, y: 0 }, Position { x: 2, y: 1 })
+            .is_err());
+        assert!(grid
+            .cropped(Position { x: 0, y: 0 }, Position { x: 0, y: 1 })
+            .is_err());
+        assert!(grid
+            .cropped(Position { x: -1, y: 0 }, Position { x: 1, y: 1 })
+            .is_err());
+    }
+
+    #[test]
+    fn tile_transforms_are_consistent() {
+        let tile = Tile {
+            typ: TileType::Belt(true, Direction::Up),
+            walls: DirectionBools {
+                up: true,
+                left: true,
+                ..DirectionBools::default()
+            },
+        };
+        let rotated = tile.rotated();
+        assert_eq!(rotated.typ, TileType::Belt(true, Direction::Right));
+        assert_eq!(
+            rotated.walls,
+            DirectionBools {
+                up: true,
+                right: true,
+                ..DirectionBools::default()
+            }
+        );
+        assert_eq!(tile.rotated().rotated().rotated().rotated(), tile);
+        for horizontal in [true, false] {
+            assert_eq!(tile.mirrored(horizontal).mirrored(horizontal), tile);
+        }
+        assert_eq!(
+            Tile {
+                typ: TileType::Rotation(true),
+                walls: DirectionBools::default(),
+            }
+            .mirrored(false)
+            .typ,
+            TileType::Rotation(false)
+        );
+    }
+}
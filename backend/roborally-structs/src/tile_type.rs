@@ -18,6 +18,36 @@ pub enum TileType {
     /// `R(cw|ccw)`
     /// bool = is_clockwise
     Rotation(bool),
+    /// `C{name}`
+    /// Behaves like floor, the map script gives it behavior. Index into [`GameMap::custom_tiles`](crate::game_map::GameMap::custom_tiles)
+    Custom(u8),
+}
+
+impl TileType {
+    /// The same tile type rotated by 90 degrees clockwise
+    #[must_use]
+    pub const fn rotated(&self) -> Self {
+        match *self {
+            Self::Belt(is_fast, dir) => Self::Belt(is_fast, dir.rotated()),
+            Self::PushPanel(dir, divisor, remainder) => {
+                Self::PushPanel(dir.rotated(), divisor, remainder)
+            }
+            typ @ (Self::Void | Self::Floor | Self::Rotation(_) | Self::Custom(_)) => typ,
+        }
+    }
+
+    /// The mirror image of this tile type, see [`Direction::mirrored`]
+    #[must_use]
+    pub const fn mirrored(&self, horizontal: bool) -> Self {
+        match *self {
+            Self::Belt(is_fast, dir) => Self::Belt(is_fast, dir.mirrored(horizontal)),
+            Self::PushPanel(dir, divisor, remainder) => {
+                Self::PushPanel(dir.mirrored(horizontal), divisor, remainder)
+            }
+            Self::Rotation(is_clockwise) => Self::Rotation(!is_clockwise),
+            typ @ (Self::Void | Self::Floor | Self::Custom(_)) => typ,
+        }
+    }
 }
 
 impl Default for TileType {
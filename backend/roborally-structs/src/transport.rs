@@ -1,28 +1,179 @@
 use crate::{
     card::Card,
-    game_state::{animated_state::AnimationItem, GeneralState, ProgrammingState},
+    chat::ChatMessage,
+    game_log::LogEntry,
+    game_state::{
+        animated_state::{AnimationDelta, AnimationItem},
+        GeneralState, LobbyState, ProgrammingState, RoundSettings,
+    },
 };
 
 use serde::{Deserialize, Serialize};
 
-#[cfg_attr(feature = "server", derive(Serialize))]
+#[cfg(feature = "client")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Version of the websocket protocol, increased on every incompatible change
+pub const PROTOCOL_VERSION: u32 = 5;
+/// Oldest version the server still talks to, for clients that start with a [`ClientMessage::Hello`]
+///
+/// Messages are only converted for [`LEGACY_PROTOCOL_VERSION`], so this has to be raised together
+/// with [`PROTOCOL_VERSION`] unless a conversion for the previous version is added
+pub const MIN_PROTOCOL_VERSION: u32 = PROTOCOL_VERSION;
+/// Version of clients from before the handshake existed, which don't send a [`ClientMessage::Hello`]
+///
+/// They get their messages converted by [`legacy::LegacyServerMessage::convert`]
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features, which are only used if both sides support them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(schemars::JsonSchema))]
+pub enum Capability {
+    /// [`ServerMessage::AnimatedStateDelta`]
+    AnimationDeltas,
+    /// [`ServerMessage::Error`] instead of [`ServerMessage::Notice`]
+    ErrorCodes,
+    /// [`ServerMessage::Chat`], clients without it don't receive the chat
+    Chat,
+}
+
+/// First message of the connection, sent by the client and answered by the server
+///
+/// The server answers with the version that will be used and the capabilities both sides support
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(schemars::JsonSchema))]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Hello {
+    /// What this build supports
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: vec![
+                Capability::AnimationDeltas,
+                Capability::ErrorCodes,
+                Capability::Chat,
+            ],
+        }
+    }
+
+    /// What clients that didn't send a [`ClientMessage::Hello`] are assumed to support
+    #[must_use]
+    pub const fn legacy() -> Self {
+        Self {
+            version: LEGACY_PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+        }
+    }
+}
+
+/// Machine-readable reason of a [`GameError`]
+///
+/// The values are used as websocket close codes, from the range reserved for applications
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize), wasm_bindgen)]
+pub enum ErrorCode {
+    GameNotFound = 4000,
+    SeatNotFound = 4001,
+    /// Somebody else is connected to the seat
+    SeatTaken = 4002,
+    /// The client's protocol version is too old, it should reload
+    IncompatibleProtocol = 4003,
+    /// The client sent a message that couldn't be decoded
+    CorruptedMessage = 4004,
+    /// The client didn't answer pings, or the connection failed
+    ConnectionLost = 4005,
+    ServerShutdown = 4006,
+    /// Cards were already programmed this round
+    CardsAlreadySet = 4007,
+    /// Programmed cards aren't in the hand, or there's a wrong number of them
+    InvalidProgram = 4008,
+    GameOver = 4009,
+    /// Only the host can do this
+    NotHost = 4010,
+    /// This can only be done in the lobby, before the game starts
+    NotInLobby = 4011,
+    /// The host removed the player from the game
+    Kicked = 4012,
+    InvalidSettings = 4013,
+    /// The game can't start until all seats are taken and all players are ready
+    NotReady = 4014,
+    /// This can't be done until the host starts the game
+    NotStarted = 4015,
+    /// Too many chat messages in a short time
+    ChatRateLimited = 4016,
+    /// The chat message is empty or too long
+    InvalidChatMessage = 4017,
+    /// The host or an admin ended the game
+    GameAborted = 4018,
+}
+
+impl ErrorCode {
+    #[must_use]
+    pub const fn close_code(self) -> u16 {
+        self as u16
+    }
+}
+
+/// Error that the client can react to, along with a message for the player
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct GameError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl GameError {
+    #[must_use]
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg_attr(feature = "server", derive(Serialize, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Deserialize))]
 #[derive(Debug, Clone)]
+/// Variants are identified by their position in some encodings, so new ones go at the end
 pub enum ServerMessage {
+    /// Message for the player, sent to clients without [`Capability::ErrorCodes`]
     Notice(String),
-    GameLog(String),
+    GameLog(Vec<LogEntry>),
     GeneralState(GeneralState),
     ProgrammingState(ProgrammingState),
     AnimatedState(AnimationItem),
+    AnimatedStateDelta(AnimationDelta),
+    Hello(Hello),
+    Error(GameError),
+    Lobby(LobbyState),
+    /// New chat message, the history is replayed as separate messages after connecting
+    Chat(ChatMessage),
 }
 
 #[cfg(feature = "client")]
 pub mod wrapper {
     use wasm_bindgen::prelude::wasm_bindgen;
 
-    use crate::game_state::{animated_state::AnimationItem, GeneralState, ProgrammingState};
+    use crate::{
+        chat::ChatMessage,
+        game_state::{animated_state::AnimationItem, GeneralState, LobbyState, ProgrammingState},
+    };
 
-    use super::ServerMessage;
+    use super::{ErrorCode, ServerMessage};
 
     #[wasm_bindgen]
     pub enum ServerMessageType {
@@ -31,6 +182,11 @@ pub mod wrapper {
         GeneralState,
         ProgrammingState,
         AnimatedState,
+        AnimatedStateDelta,
+        Hello,
+        Error,
+        Lobby,
+        Chat,
     }
 
     #[wasm_bindgen(skip_all)]
@@ -47,6 +203,22 @@ pub mod wrapper {
                 ServerMessage::GeneralState(_) => ServerMessageType::GeneralState,
                 ServerMessage::ProgrammingState(_) => ServerMessageType::ProgrammingState,
                 ServerMessage::AnimatedState(_) => ServerMessageType::AnimatedState,
+                ServerMessage::AnimatedStateDelta(_) => ServerMessageType::AnimatedStateDelta,
+                ServerMessage::Hello(_) => ServerMessageType::Hello,
+                ServerMessage::Error(_) => ServerMessageType::Error,
+                ServerMessage::Lobby(_) => ServerMessageType::Lobby,
+                ServerMessage::Chat(_) => ServerMessageType::Chat,
+            }
+        }
+
+        /// Protocol version chosen by the server
+        #[wasm_bindgen(getter)]
+        #[must_use]
+        pub fn protocol_version(&self) -> u32 {
+            if let ServerMessage::Hello(hello) = &self.0 {
+                hello.version
+            } else {
+                panic!("Tried to get protocol_version from different message type");
             }
         }
 
@@ -60,11 +232,32 @@ pub mod wrapper {
             }
         }
 
+        #[wasm_bindgen(getter)]
+        #[must_use]
+        pub fn error_code(&self) -> ErrorCode {
+            if let ServerMessage::Error(e) = &self.0 {
+                e.code
+            } else {
+                panic!("Tried to get error_code from different message type");
+            }
+        }
+
+        #[wasm_bindgen(getter)]
+        #[must_use]
+        pub fn error_message(&self) -> String {
+            if let ServerMessage::Error(e) = &self.0 {
+                e.message.clone()
+            } else {
+                panic!("Tried to get error_message from different message type");
+            }
+        }
+
+        /// Log entries formatted as text, one per line
         #[wasm_bindgen(getter)]
         #[must_use]
         pub fn game_log(&self) -> String {
-            if let ServerMessage::GameLog(s) = &self.0 {
-                s.clone()
+            if let ServerMessage::GameLog(entries) = &self.0 {
+                entries.iter().map(|e| format!("{e}\n")).collect()
             } else {
                 panic!("Tried to get game_log from different message type");
             }
@@ -80,6 +273,16 @@ pub mod wrapper {
             }
         }
 
+        #[wasm_bindgen(getter)]
+        #[must_use]
+        pub fn lobby_state(&self) -> LobbyState {
+            if let ServerMessage::Lobby(s) = &self.0 {
+                s.clone()
+            } else {
+                panic!("Tried to get lobby_state from different message type");
+            }
+        }
+
         #[wasm_bindgen(getter)]
         #[must_use]
         pub fn programming_state(&self) -> ProgrammingState {
@@ -99,11 +302,176 @@ pub mod wrapper {
                 panic!("Tried to get animated_state from different message type");
             }
         }
+
+        /// Applies the delta on top of `previous`, which must be the last received state
+        #[must_use]
+        pub fn apply_animated_state_delta(&self, previous: &AnimationItem) -> AnimationItem {
+            if let ServerMessage::AnimatedStateDelta(delta) = &self.0 {
+                delta.apply(previous)
+            } else {
+                panic!("Tried to apply animated_state_delta from different message type");
+            }
+        }
+
+        #[wasm_bindgen(getter)]
+        #[must_use]
+        pub fn chat_message(&self) -> ChatMessage {
+            if let ServerMessage::Chat(m) = &self.0 {
+                m.clone()
+            } else {
+                panic!("Tried to get chat_message from different message type");
+            }
+        }
+    }
+}
+
+/// Messages in the form that clients of [`LEGACY_PROTOCOL_VERSION`] decode
+#[cfg(feature = "server")]
+pub mod legacy {
+    use serde::Serialize;
+
+    use crate::game_state::{
+        animated_state::AnimationItem, GameStatusInfo, GeneralState, ProgrammingState,
+    };
+
+    use super::ServerMessage;
+
+    #[derive(Serialize, Debug)]
+    pub enum LegacyGameStatus {
+        Programming,
+        Processing,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct LegacyGeneralState {
+        pub player_names: Vec<Option<String>>,
+        pub status: LegacyGameStatus,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub enum LegacyServerMessage {
+        Notice(String),
+        /// Log entries formatted as text, one per line
+        GameLog(String),
+        GeneralState(LegacyGeneralState),
+        ProgrammingState(ProgrammingState),
+        AnimatedState(AnimationItem),
+    }
+
+    impl LegacyServerMessage {
+        /// Returns `None` for messages that these clients don't know, they aren't sent at all
+        ///
+        /// The lobby is shown as waiting for programming, a finished game as still being evaluated
+        #[must_use]
+        pub fn convert(msg: &ServerMessage) -> Option<Self> {
+            Some(match msg {
+                ServerMessage::Notice(s) => Self::Notice(s.clone()),
+                ServerMessage::Error(e) => Self::Notice(e.message.clone()),
+                ServerMessage::GameLog(entries) => {
+                    Self::GameLog(entries.iter().map(|e| format!("{e}\n")).collect())
+                }
+                ServerMessage::GeneralState(GeneralState {
+                    player_names,
+                    status,
+                    ..
+                }) => Self::GeneralState(LegacyGeneralState {
+                    player_names: player_names.clone(),
+                    status: match status {
+                        GameStatusInfo::Programming | GameStatusInfo::Lobby => {
+                            LegacyGameStatus::Programming
+                        }
+                        GameStatusInfo::Processing | GameStatusInfo::Finished(_) => {
+                            LegacyGameStatus::Processing
+                        }
+                    },
+                }),
+                ServerMessage::ProgrammingState(s) => Self::ProgrammingState(s.clone()),
+                ServerMessage::AnimatedState(item) => Self::AnimatedState(item.clone()),
+                ServerMessage::AnimatedStateDelta(_)
+                | ServerMessage::Hello(_)
+                | ServerMessage::Lobby(_)
+                | ServerMessage::Chat(_) => return None,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+        use crate::{
+            chat::ChatMessage,
+            game_log::{LogEntry, LogLevel},
+            transport::Hello,
+        };
+
+        fn converted(msg: &ServerMessage) -> serde_json::Value {
+            serde_json::to_value(LegacyServerMessage::convert(msg).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn game_log_is_sent_as_text() {
+            let entry = LogEntry {
+                level: LogLevel::Error,
+                card: Some("Teleport".to_owned()),
+                player_i: Some(1),
+                register_i: None,
+                message: "boom".to_owned(),
+            };
+            assert_eq!(
+                converted(&ServerMessage::GameLog(vec![entry])),
+                json!({"GameLog": "[Teleport, player 1] error: boom\n"})
+            );
+        }
+
+        #[test]
+        fn general_state_has_only_old_fields() {
+            let msg = ServerMessage::GeneralState(GeneralState {
+                player_names: vec![Some("a".to_owned()), None],
+                status: GameStatusInfo::Lobby,
+                host: Some(0),
+                paused: false,
+            });
+            assert_eq!(
+                converted(&msg),
+                json!({"GeneralState": {"player_names": ["a", null], "status": "Programming"}})
+            );
+        }
+
+        #[test]
+        fn unknown_messages_are_dropped() {
+            let chat = ServerMessage::Chat(ChatMessage {
+                seat: None,
+                author: String::new(),
+                text: "hi".to_owned(),
+            });
+            assert!(LegacyServerMessage::convert(&chat).is_none());
+            let hello = ServerMessage::Hello(Hello::current());
+            assert!(LegacyServerMessage::convert(&hello).is_none());
+        }
     }
 }
 
-#[cfg_attr(feature = "server", derive(Deserialize, Debug))]
+#[cfg_attr(feature = "server", derive(Deserialize, Debug, schemars::JsonSchema))]
 #[cfg_attr(feature = "client", derive(Serialize))]
 pub enum ClientMessage {
     Program(Vec<Card>),
+    Hello(Hello),
+    /// In the lobby, tell the host whether this player is ready to start
+    SetReady(bool),
+    /// Moves to a free seat, only in the lobby
+    SwitchSeat(usize),
+    /// Host only, in the lobby
+    ChangeSettings(RoundSettings),
+    /// Host only, disconnects the player in given seat, so that somebody else can take it
+    Kick(usize),
+    /// Host only, assigns spawn points and deals the first hands
+    StartGame,
+    /// Chat message text, at most [`crate::chat::MAX_CHAT_MESSAGE_LENGTH`] characters
+    Chat(String),
+    /// Host only, stops or continues evaluating the round before the next register
+    SetPaused(bool),
+    /// Host only, disconnects everyone and deletes the game
+    AbortGame,
 }
@@ -2,6 +2,6 @@
 
 for file in savefiles/*.bin
     echo Uploading $file
-    curl -Ss "http://localhost:3000/api/savefile?game_name=%5Bloaded%20savefile%5D%20"$file -X POST --upload-file $file
+    curl -Ss "http://localhost:3000/api/savefile?game_name=%5Bloaded%20savefile%5D%20"$file -X POST --upload-file $file -H "Authorization: Bearer $ADMIN_TOKEN"
     echo
 end